@@ -1,232 +1,509 @@
-use common::enums::{CropPosition, Game, Operation};
-use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use common::enums::{Anchor, CropPosition, OriginalsAction, OutputFormat, ResizeFilter, ResizeMode, WebPMode};
+use common::structs::{AspectRatio, BlurRegion, OutputOption, ScanOption};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::options::RESERVED_OPERATIONS;
+
+/// Name of the game profile used when '-g|--game' is not specified
+pub const NO_GAME: &str = "none";
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct TomlConfig {
   general: GeneralSection,
-  game: GameSection,
+  output: OutputOption,
+  #[serde(deserialize_with = "with_no_game")]
+  game: BTreeMap<String, GameProfile>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct GeneralSection {
-  folder_name: FolderNameSection,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(default)]
-pub struct FolderNameSection {
-  background: String,
-  center: String,
-  cutscene: String,
-  foreground0: String,
-  foreground1: String,
-  foreground2: String,
-  foreground3: String,
-  foreground4: String,
-  foreground5: String,
-  full: String,
+  scan: ScanOption,
+  /// Folder name of every layer, from before layers had their own `folder_name`. Read to move it into the layers,
+  /// never written.
+  #[serde(skip_serializing)]
+  folder_name: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
-pub struct GameSection {
-  wuwa: Option<WuWaSection>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(default)]
-pub struct WuWaSection {
-  background: LayerConfig,
-  center: LayerConfig,
-  cutscene: LayerConfig,
-  foreground0: LayerConfig,
-  foreground1: LayerConfig,
-  foreground2: LayerConfig,
-  foreground3: LayerConfig,
-  foreground4: LayerConfig,
-  foreground5: LayerConfig,
-  full: LayerConfig,
+pub struct GameProfile {
+  /// Other names accepted by '-g|--game'
+  #[serde(skip_serializing_if = "Option::is_none")]
+  aliases: Option<Vec<String>>,
+  /// Resolution that 'blur' and 'crop_height' of every layer are given in, as [width, height]
+  reference: [u32; 2],
+  /// Only convert images of this width, 0 for any width, unless a layer sets its own (default: 0)
+  width_from: Option<u32>,
+  /// Width of converted images, unless a layer sets its own (default: 1280, or 0 to keep the width with 'none')
+  width_to: Option<u32>,
+  /// Every other key is a layer
+  #[serde(flatten)]
+  layers: BTreeMap<String, LayerConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct LayerConfig {
+  /// Directory under the target directory holding the screenshots of this layer (default: 'CS-<layer name>')
+  #[serde(skip_serializing_if = "Option::is_none")]
+  folder_name: Option<String>,
+  /// Other names accepted as operation
+  #[serde(skip_serializing_if = "Option::is_none")]
+  aliases: Option<Vec<String>>,
   crop_height: u32,
   crop_position: CropPosition,
-  blur: Vec<[u32; 4]>,
+  /// Keep the largest area of this ratio, like "21:9", within the area kept by 'crop_position'
+  crop_aspect: Option<AspectRatio>,
+  /// Where the 'crop_aspect' area sits (default: 'center')
+  crop_anchor: Option<Anchor>,
+  /// Brightest channel value of a dark bar removed by 'crop_position = "auto"' (default: 24)
+  auto_crop_threshold: Option<u8>,
+  blur: Vec<BlurRegion>,
+  /// Overrides 'width_from' of the game
+  width_from: Option<u32>,
+  /// Overrides 'width_to' of the game
+  width_to: Option<u32>,
+  /// How the cropped image is resized, such as { fit_width = 1280 } (default: by 'width_to')
+  resize: Option<ResizeMode>,
+  /// Resampling filter used to resize (default: 'lanczos3')
+  resize_filter: Option<ResizeFilter>,
+  format: Option<OutputFormat>,
+  webp_mode: Option<WebPMode>,
+  /// What happens to every image once its output is written and verified (default: 'keep')
+  originals: Option<OriginalsAction>,
 }
 
-impl Default for TomlConfig {
-  fn default() -> Self {
-    TomlConfig { general: GeneralSection::default(), game: GameSection::default() }
+impl TomlConfig {
+  pub fn blur(&self, game: &str, layer: &str) -> Vec<BlurRegion> {
+    self.layer(game, layer).blur.clone()
   }
-}
 
-impl TomlConfig {
-  pub fn blur(&self, game: Game, op: Operation) -> Vec<[u32; 4]> {
-    match game {
-      Game::None => Vec::new(),
-      Game::WuWa => {
-        let wuwa = self.game.wuwa.as_ref().expect("WuWa config must be present but could not find it.");
-        match op {
-          Operation::Background => wuwa.background.blur.clone(),
-          Operation::Center => wuwa.center.blur.clone(),
-          Operation::Cutscene => wuwa.cutscene.blur.clone(),
-          Operation::Foreground0 => wuwa.foreground0.blur.clone(),
-          Operation::Foreground1 => wuwa.foreground1.blur.clone(),
-          Operation::Foreground2 => wuwa.foreground2.blur.clone(),
-          Operation::Foreground3 => wuwa.foreground3.blur.clone(),
-          Operation::Foreground4 => wuwa.foreground4.blur.clone(),
-          Operation::Foreground5 => wuwa.foreground5.blur.clone(),
-          Operation::Full => wuwa.full.blur.clone(),
-          _ => Vec::new(),
-        }
+  pub fn crop_height(&self, game: &str, layer: &str) -> u32 {
+    self.layer(game, layer).crop_height
+  }
+
+  pub fn crop_position(&self, game: &str, layer: &str) -> CropPosition {
+    self.layer(game, layer).crop_position
+  }
+
+  pub fn crop_aspect(&self, game: &str, layer: &str) -> Option<AspectRatio> {
+    self.layer(game, layer).crop_aspect
+  }
+
+  pub fn crop_anchor(&self, game: &str, layer: &str) -> Option<Anchor> {
+    self.layer(game, layer).crop_anchor
+  }
+
+  pub fn auto_crop_threshold(&self, game: &str, layer: &str) -> u8 {
+    self.layer(game, layer).auto_crop_threshold.unwrap_or(24)
+  }
+
+  pub fn width_from(&self, game: &str, layer: &str) -> Option<u32> {
+    self.layer(game, layer).width_from.or(self.profile(game).width_from)
+  }
+
+  pub fn width_to(&self, game: &str, layer: &str) -> Option<u32> {
+    self.layer(game, layer).width_to.or(self.profile(game).width_to)
+  }
+
+  pub fn resize(&self, game: &str, layer: &str) -> Option<ResizeMode> {
+    self.layer(game, layer).resize
+  }
+
+  pub fn resize_filter(&self, game: &str, layer: &str) -> Option<ResizeFilter> {
+    self.layer(game, layer).resize_filter
+  }
+
+  pub fn format(&self, game: &str, layer: &str) -> Option<OutputFormat> {
+    self.layer(game, layer).format
+  }
+
+  pub fn webp_mode(&self, game: &str, layer: &str) -> Option<WebPMode> {
+    self.layer(game, layer).webp_mode
+  }
+
+  pub fn originals(&self, game: &str, layer: &str) -> Option<OriginalsAction> {
+    self.layer(game, layer).originals
+  }
+
+  pub fn folder_name(&self, game: &str, layer: &str) -> String {
+    self.layer(game, layer).folder_name.clone().unwrap_or_else(|| format!("CS-{}", layer))
+  }
+
+  pub fn reference(&self, game: &str) -> [u32; 2] {
+    self.profile(game).reference
+  }
+
+  /// Returns the names of every layer of `game`.
+  pub fn layers(&self, game: &str) -> Vec<String> {
+    self.profile(game).layers.keys().cloned().collect()
+  }
+
+  /// Returns the folder names created by 'create-directory' for `game`. Without a game, that is the folder of every
+  /// layer of every game, so a plain `cs cd` still sets up folders for all of them.
+  pub fn directories(&self, game: &str) -> BTreeSet<String> {
+    let games: Vec<&str> = if game == NO_GAME { self.game.keys().map(String::as_str).collect() } else { vec![game] };
+    games
+      .into_iter()
+      .flat_map(|game| self.layers(game).into_iter().map(move |layer| self.folder_name(game, &layer)))
+      .collect()
+  }
+
+  /// Resolves the name or alias of a game given to '-g|--game' to the name of its `[game.<name>]` table.
+  pub fn resolve_game(&self, name: &str) -> Result<String, String> {
+    if self.game.contains_key(name) {
+      return Ok(name.to_string());
+    }
+    match self.game.iter().find(|(_, profile)| profile.aliases.iter().flatten().any(|a| a == name)) {
+      Some((key, _)) => Ok(key.clone()),
+      None => {
+        let names: Vec<&str> = self.game.keys().map(String::as_str).collect();
+        Err(format!("Unknown game '{}'. Available games: {}", name, names.join(", ")))
       },
     }
   }
 
-  pub fn crop_height(&self, game: Game, op: Operation) -> u32 {
-    match game {
-      Game::None => 0,
-      Game::WuWa => {
-        let wuwa = self.game.wuwa.as_ref().expect("WuWa config must be present but could not find it.");
-        match op {
-          Operation::Background => wuwa.background.crop_height,
-          Operation::Center => wuwa.center.crop_height,
-          Operation::Cutscene => wuwa.cutscene.crop_height,
-          Operation::Foreground0 => wuwa.foreground0.crop_height,
-          Operation::Foreground1 => wuwa.foreground1.crop_height,
-          Operation::Foreground2 => wuwa.foreground2.crop_height,
-          Operation::Foreground3 => wuwa.foreground3.crop_height,
-          Operation::Foreground4 => wuwa.foreground4.crop_height,
-          Operation::Foreground5 => wuwa.foreground5.crop_height,
-          Operation::Full => wuwa.full.crop_height,
-          _ => 0,
-        }
+  /// Resolves the name or alias of a layer given as operation to the name of its `[game.<game>.<name>]` table.
+  /// `game` must be resolved with [`TomlConfig::resolve_game`] beforehand.
+  pub fn resolve_layer(&self, game: &str, name: &str) -> Result<String, String> {
+    let profile = self.profile(game);
+    if profile.layers.contains_key(name) {
+      return Ok(name.to_string());
+    }
+    match profile.layers.iter().find(|(_, layer)| layer.aliases.iter().flatten().any(|a| a == name)) {
+      Some((key, _)) => Ok(key.clone()),
+      None => {
+        let names: Vec<&str> = profile.layers.keys().map(String::as_str).collect();
+        Err(format!("Game '{}' has no layer '{}'. Available layers: {}", game, name, names.join(", ")))
       },
     }
   }
 
-  pub fn crop_position(&self, game: Game, op: Operation) -> CropPosition {
-    match game {
-      Game::None => CropPosition::Full,
-      Game::WuWa => {
-        let wuwa = self.game.wuwa.as_ref().expect("WuWa config must be present but could not find it.");
-        match op {
-          Operation::Background => wuwa.background.crop_position,
-          Operation::Center => wuwa.center.crop_position,
-          Operation::Cutscene => wuwa.cutscene.crop_position,
-          Operation::Foreground0 => wuwa.foreground0.crop_position,
-          Operation::Foreground1 => wuwa.foreground1.crop_position,
-          Operation::Foreground2 => wuwa.foreground2.crop_position,
-          Operation::Foreground3 => wuwa.foreground3.crop_position,
-          Operation::Foreground4 => wuwa.foreground4.crop_position,
-          Operation::Foreground5 => wuwa.foreground5.crop_position,
-          _ => wuwa.full.crop_position,
+  /// `game` must be resolved with [`TomlConfig::resolve_game`] beforehand.
+  fn profile(&self, game: &str) -> &GameProfile {
+    self.game.get(game).expect("Game profile must be present but could not find it.")
+  }
+
+  /// `layer` must be resolved with [`TomlConfig::resolve_layer`] beforehand.
+  fn layer(&self, game: &str, layer: &str) -> &LayerConfig {
+    self.profile(game).layers.get(layer).expect("Layer must be present but could not find it.")
+  }
+
+  /// Moves `[general.folder_name]` into the layers of the same name, and fills in the aliases and folder names that a
+  /// profile named like a built-in one leaves unset, as files written before profiles had them still rely on them.
+  /// Fails on a `[general.folder_name]` key that names no layer, rather than dropping it, and on a layer named or
+  /// aliased like an operation, which could never be selected.
+  pub fn fill_defaults(mut self) -> Result<Self, String> {
+    for (layer_name, folder_name) in std::mem::take(&mut self.general.folder_name) {
+      let mut layers = self.game.values_mut().filter_map(|profile| profile.layers.get_mut(&layer_name)).peekable();
+      if layers.peek().is_none() {
+        return Err(format!(
+          "[general.folder_name] is no longer supported and no layer is named '{}'. Set 'folder_name = \"{}\"' in the \
+           [game.<game>.<layer>] table it belongs to instead",
+          layer_name, folder_name
+        ));
+      }
+      for layer in layers {
+        layer.folder_name.get_or_insert_with(|| folder_name.clone());
+      }
+    }
+    for (name, profile) in &mut self.game {
+      let Some(builtin) = GameProfile::builtin(name) else {
+        continue;
+      };
+      profile.aliases = profile.aliases.take().or(builtin.aliases);
+      for (layer_name, builtin_layer) in builtin.layers {
+        if let Some(layer) = profile.layers.get_mut(&layer_name) {
+          layer.aliases = layer.aliases.take().or(builtin_layer.aliases);
+          layer.folder_name = layer.folder_name.take().or(builtin_layer.folder_name);
         }
-      },
+      }
     }
+    for (game, profile) in &self.game {
+      for (layer_name, layer) in &profile.layers {
+        let mut names = std::iter::once(layer_name).chain(layer.aliases.iter().flatten());
+        if let Some(name) = names.find(|name| RESERVED_OPERATIONS.contains(&name.as_str())) {
+          return Err(format!(
+            "Layer '{}' of game '{}' cannot be named or aliased '{}', as it is an operation. Reserved names: {}",
+            layer_name,
+            game,
+            name,
+            RESERVED_OPERATIONS.join(", ")
+          ));
+        }
+      }
+    }
+    Ok(self)
   }
 
-  pub fn folder_name(&self, op: Operation) -> Option<String> {
-    match op {
-      Operation::Background => Some(self.general.folder_name.background.clone()),
-      Operation::Center => Some(self.general.folder_name.center.clone()),
-      Operation::Cutscene => Some(self.general.folder_name.cutscene.clone()),
-      Operation::Foreground0 => Some(self.general.folder_name.foreground0.clone()),
-      Operation::Foreground1 => Some(self.general.folder_name.foreground1.clone()),
-      Operation::Foreground2 => Some(self.general.folder_name.foreground2.clone()),
-      Operation::Foreground3 => Some(self.general.folder_name.foreground3.clone()),
-      Operation::Foreground4 => Some(self.general.folder_name.foreground4.clone()),
-      Operation::Foreground5 => Some(self.general.folder_name.foreground5.clone()),
-      Operation::Full => Some(self.general.folder_name.full.clone()),
-      _ => None,
-    }
+  pub fn scan(&self) -> &ScanOption {
+    &self.general.scan
   }
+
+  pub fn output(&self) -> &OutputOption {
+    &self.output
+  }
+}
+
+/// Adds the default [`NO_GAME`] profile if the TOML file lacks one.
+fn with_no_game<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, GameProfile>, D::Error> {
+  let mut game = BTreeMap::deserialize(deserializer)?;
+  game.entry(NO_GAME.to_string()).or_insert_with(GameProfile::none);
+  Ok(game)
 }
 
-impl Default for GeneralSection {
+impl Default for TomlConfig {
   fn default() -> Self {
-    GeneralSection { folder_name: FolderNameSection::default() }
+    TomlConfig {
+      general: GeneralSection::default(),
+      output: OutputOption::default(),
+      game: BTreeMap::from([(NO_GAME.to_string(), GameProfile::none()), ("wuwa".to_string(), GameProfile::wuwa())]),
+    }
   }
 }
 
-impl Default for FolderNameSection {
-  fn default() -> Self {
-    FolderNameSection {
-      background: "CS-Background".into(),
-      center: "CS-Center".into(),
-      cutscene: "CS-Cutscene".into(),
-      foreground0: "CS-Foreground-0".into(),
-      foreground1: "CS-Foreground-1".into(),
-      foreground2: "CS-Foreground-2".into(),
-      foreground3: "CS-Foreground-3".into(),
-      foreground4: "CS-Foreground-4".into(),
-      foreground5: "CS-Foreground-5".into(),
-      full: "CS-Full".into(),
+impl GameProfile {
+  /// Returns the built-in profile named `name`, if any.
+  fn builtin(name: &str) -> Option<GameProfile> {
+    match name {
+      NO_GAME => Some(GameProfile::none()),
+      "wuwa" => Some(GameProfile::wuwa()),
+      _ => None,
+    }
+  }
+
+  fn none() -> Self {
+    GameProfile {
+      aliases: Some(vec!["n".into()]),
+      reference: [1920, 1080],
+      width_from: None,
+      width_to: None,
+      layers: BTreeMap::from([("full".into(), LayerConfig::new("CS-Full", &["f"], 0, CropPosition::Full, Vec::new()))]),
+    }
+  }
+
+  fn wuwa() -> Self {
+    GameProfile {
+      aliases: Some(vec!["w".into()]),
+      reference: [1920, 1080],
+      width_from: None,
+      width_to: Some(1280),
+      layers: BTreeMap::from([
+        (
+          "background".into(),
+          LayerConfig::new("CS-Background", &["b", "bg"], 360, CropPosition::Bottom, vec![[40, 1054, 330, 22], [
+            1733, 1058, 140, 22,
+          ]]),
+        ),
+        ("center".into(), LayerConfig::new("CS-Center", &["c"], 200, CropPosition::Center, Vec::new())),
+        (
+          "cutscene".into(),
+          LayerConfig::new("CS-Cutscene", &["cs", "s"], 810, CropPosition::Center, vec![[1781, 927, 110, 16]]),
+        ),
+        (
+          "foreground0".into(),
+          LayerConfig::new("CS-Foreground-0", &["f0", "fg0"], 310, CropPosition::Bottom, vec![[1733, 1058, 140, 22]]),
+        ),
+        (
+          "foreground1".into(),
+          LayerConfig::new("CS-Foreground-1", &["f1", "fg1"], 420, CropPosition::Bottom, vec![[1733, 1058, 140, 22]]),
+        ),
+        (
+          "foreground2".into(),
+          LayerConfig::new("CS-Foreground-2", &["f2", "fg2"], 505, CropPosition::Bottom, vec![[1733, 1058, 140, 22]]),
+        ),
+        (
+          "foreground3".into(),
+          LayerConfig::new("CS-Foreground-3", &["f3", "fg3"], 580, CropPosition::Bottom, vec![[1733, 1058, 140, 22]]),
+        ),
+        (
+          "foreground4".into(),
+          LayerConfig::new("CS-Foreground-4", &["f4", "fg4"], 655, CropPosition::Bottom, vec![[1733, 1058, 140, 22]]),
+        ),
+        (
+          "foreground5".into(),
+          LayerConfig::new("CS-Foreground-5", &["f5", "fg5"], 730, CropPosition::Bottom, vec![[1733, 1058, 140, 22]]),
+        ),
+        (
+          "full".into(),
+          LayerConfig::new("CS-Full", &["f"], 0, CropPosition::Full, vec![[40, 1054, 330, 22], [1733, 1058, 140, 22]]),
+        ),
+      ]),
     }
   }
 }
 
-impl Default for GameSection {
+impl Default for GameProfile {
   fn default() -> Self {
-    GameSection { wuwa: Some(WuWaSection::default()) }
+    GameProfile { aliases: None, reference: [1920, 1080], width_from: None, width_to: None, layers: BTreeMap::new() }
   }
 }
 
-impl Default for WuWaSection {
-  fn default() -> Self {
-    WuWaSection {
-      background: LayerConfig {
-        crop_height: 360,
-        crop_position: CropPosition::Bottom,
-        blur: vec![[40, 1054, 330, 22], [1733, 1058, 140, 22]],
-      },
-      center: LayerConfig { crop_height: 200, crop_position: CropPosition::Center, blur: Vec::new() },
-      cutscene: LayerConfig { crop_height: 810, crop_position: CropPosition::Center, blur: vec![[1781, 927, 110, 16]] },
-      foreground0: LayerConfig {
-        crop_height: 310,
-        crop_position: CropPosition::Bottom,
-        blur: vec![[1733, 1058, 140, 22]],
-      },
-      foreground1: LayerConfig {
-        crop_height: 420,
-        crop_position: CropPosition::Bottom,
-        blur: vec![[1733, 1058, 140, 22]],
-      },
-      foreground2: LayerConfig {
-        crop_height: 505,
-        crop_position: CropPosition::Bottom,
-        blur: vec![[1733, 1058, 140, 22]],
-      },
-      foreground3: LayerConfig {
-        crop_height: 580,
-        crop_position: CropPosition::Bottom,
-        blur: vec![[1733, 1058, 140, 22]],
-      },
-      foreground4: LayerConfig {
-        crop_height: 655,
-        crop_position: CropPosition::Bottom,
-        blur: vec![[1733, 1058, 140, 22]],
-      },
-      foreground5: LayerConfig {
-        crop_height: 730,
-        crop_position: CropPosition::Bottom,
-        blur: vec![[1733, 1058, 140, 22]],
-      },
-      full: LayerConfig {
-        crop_height: 0,
-        crop_position: CropPosition::Full,
-        blur: vec![[40, 1054, 330, 22], [1733, 1058, 140, 22]],
-      },
+impl LayerConfig {
+  fn new(
+    folder_name: &str,
+    aliases: &[&str],
+    crop_height: u32,
+    crop_position: CropPosition,
+    blur: Vec<[u32; 4]>,
+  ) -> Self {
+    LayerConfig {
+      folder_name: Some(folder_name.into()),
+      aliases: Some(aliases.iter().map(|a| a.to_string()).collect()),
+      crop_height,
+      crop_position,
+      blur: blur.into_iter().map(BlurRegion::from).collect(),
+      ..Default::default()
     }
   }
 }
 
 impl Default for LayerConfig {
   fn default() -> Self {
-    LayerConfig { crop_height: 0, crop_position: CropPosition::Full, blur: Vec::new() }
+    LayerConfig {
+      folder_name: None,
+      aliases: None,
+      crop_height: 0,
+      crop_position: CropPosition::Full,
+      crop_aspect: None,
+      crop_anchor: None,
+      auto_crop_threshold: None,
+      blur: Vec::new(),
+      width_from: None,
+      width_to: None,
+      resize: None,
+      resize_filter: None,
+      format: None,
+      webp_mode: None,
+      originals: None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// `cs.toml` as written before layers had their own `folder_name` and `aliases`, with one folder renamed
+  const LEGACY_TOML: &str = r#"
+[general.folder_name]
+background = "My-BG"
+center = "CS-Center"
+cutscene = "CS-Cutscene"
+foreground0 = "CS-Foreground-0"
+foreground1 = "CS-Foreground-1"
+foreground2 = "CS-Foreground-2"
+foreground3 = "CS-Foreground-3"
+foreground4 = "CS-Foreground-4"
+foreground5 = "CS-Foreground-5"
+full = "CS-Full"
+
+[game.wuwa.background]
+crop_height = 360
+crop_position = "bottom"
+blur = [[40, 1054, 330, 22], [1733, 1058, 140, 22]]
+
+[game.wuwa.center]
+crop_height = 200
+crop_position = "center"
+blur = []
+
+[game.wuwa.cutscene]
+crop_height = 810
+crop_position = "center"
+blur = [[1781, 929, 110, 16]]
+"#;
+
+  fn load(toml: &str) -> Result<TomlConfig, String> {
+    toml::from_str::<TomlConfig>(toml).map_err(|e| e.to_string())?.fill_defaults()
+  }
+
+  fn legacy_with_foregrounds() -> String {
+    let mut toml = LEGACY_TOML.to_string();
+    for (i, height) in [310, 420, 505, 580, 655, 730].into_iter().enumerate() {
+      toml += &format!("\n[game.wuwa.foreground{}]\ncrop_height = {}\ncrop_position = \"bottom\"\n", i, height);
+    }
+    toml + "\n[game.wuwa.full]\ncrop_height = 0\ncrop_position = \"full\"\n"
+  }
+
+  #[test]
+  fn legacy_file_keeps_layers_and_folder_names() {
+    let config = load(&legacy_with_foregrounds()).unwrap();
+    assert_eq!(config.layers("wuwa"), [
+      "background",
+      "center",
+      "cutscene",
+      "foreground0",
+      "foreground1",
+      "foreground2",
+      "foreground3",
+      "foreground4",
+      "foreground5",
+      "full"
+    ]);
+    assert_eq!(config.layers(NO_GAME), ["full"]);
+    assert_eq!(config.folder_name("wuwa", "background"), "My-BG");
+    assert_eq!(config.folder_name("wuwa", "foreground3"), "CS-Foreground-3");
+    assert_eq!(config.folder_name("wuwa", "full"), "CS-Full");
+    assert_eq!(config.folder_name(NO_GAME, "full"), "CS-Full");
+  }
+
+  #[test]
+  fn legacy_file_gets_builtin_aliases() {
+    let config = load(&legacy_with_foregrounds()).unwrap();
+    assert_eq!(config.resolve_game("w"), Ok("wuwa".to_string()));
+    assert_eq!(config.resolve_game("n"), Ok(NO_GAME.to_string()));
+    assert_eq!(config.resolve_layer("wuwa", "bg"), Ok("background".to_string()));
+    assert_eq!(config.resolve_layer("wuwa", "s"), Ok("cutscene".to_string()));
+    assert_eq!(config.resolve_layer("wuwa", "fg5"), Ok("foreground5".to_string()));
+    assert_eq!(config.resolve_layer(NO_GAME, "f"), Ok("full".to_string()));
+    assert!(config.resolve_layer("wuwa", "x").is_err());
+  }
+
+  #[test]
+  fn legacy_folder_name_of_missing_layer_fails() {
+    // the foreground and full layers named in [general.folder_name] are missing
+    let err = load(LEGACY_TOML).unwrap_err();
+    assert!(err.contains("no layer is named 'foreground0'"), "{}", err);
+  }
+
+  #[test]
+  fn layer_settings_win_over_defaults() {
+    let config = load(
+      r#"
+[general.folder_name]
+center = "Legacy-Center"
+
+[game.wuwa]
+aliases = ["ww"]
+
+[game.wuwa.center]
+folder_name = "Middle"
+aliases = ["m"]
+
+[game.genshin.menu]
+crop_position = "full"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.folder_name("wuwa", "center"), "Middle");
+    assert_eq!(config.resolve_layer("wuwa", "m"), Ok("center".to_string()));
+    assert!(config.resolve_layer("wuwa", "c").is_err());
+    assert_eq!(config.resolve_game("ww"), Ok("wuwa".to_string()));
+    assert!(config.resolve_game("w").is_err());
+    assert_eq!(config.layers("genshin"), ["menu"]);
+    assert_eq!(config.folder_name("genshin", "menu"), "CS-menu");
+  }
+
+  #[test]
+  fn layer_named_like_operation_fails() {
+    for toml in ["[game.wuwa.watch]\n", "[game.wuwa.center]\naliases = [\"a\"]\n"] {
+      let err = load(toml).unwrap_err();
+      assert!(err.contains("as it is an operation"), "{}", err);
+    }
   }
 }
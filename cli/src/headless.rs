@@ -0,0 +1,59 @@
+use std::cell::Cell;
+
+use common::pipeline::{ImageMsg, Run, process_image};
+use common::structs::MergedOption;
+use common::{dir_collect_image, output_dir};
+
+/// Number of images converted, skipped and failed in a headless run.
+#[derive(Default)]
+pub struct Summary {
+  pub done: usize,
+  pub skipped: usize,
+  pub failed: usize,
+}
+
+/// Runs the conversion in this process and prints the progress to the terminal.
+pub fn run_headless(mo: &MergedOption) -> Summary {
+  #[cfg(debug_assertions)]
+  println!("DEBUG: Content of MergedOption: {:#?}", mo);
+
+  let imgs = dir_collect_image(&mo.target, &mo.scan);
+  if imgs.is_empty() {
+    eprintln!("There is no image file in '{}'", mo.target.display());
+    return Summary { failed: 1, ..Default::default() };
+  }
+
+  let to = output_dir(mo);
+  println!("Files From: {}", mo.target.display());
+  println!("Saved To:   {}", to.display());
+  println!("Game: {} | Layer: {}", mo.game, mo.layer);
+
+  let done = Cell::new(0);
+  let skipped = Cell::new(0);
+  let failed = Cell::new(0);
+  process_image(imgs, mo, to, Run::default(), &|msg| match msg {
+    ImageMsg::Done { filename } => {
+      done.set(done.get() + 1);
+      println!("✔ {} DONE", filename);
+    },
+    ImageMsg::Error { text } => {
+      failed.set(failed.get() + 1);
+      eprintln!("✖ {}", text);
+    },
+    ImageMsg::Finished => {
+      println!("Finished: {} done, {} skipped, {} failed", done.get(), skipped.get(), failed.get());
+    },
+    ImageMsg::Progress { current, total, filename } => {
+      println!("[{}/{}] → {}", current, total, filename);
+    },
+    ImageMsg::Skipped { filename } => {
+      skipped.set(skipped.get() + 1);
+      println!("↷ {} SKIPPED", filename);
+    },
+    ImageMsg::Warning { text } => {
+      eprintln!("⚠ {}", text);
+    },
+  });
+
+  Summary { done: done.get(), skipped: skipped.get(), failed: failed.get() }
+}
@@ -1,5 +1,8 @@
 mod config;
+mod headless;
 mod options;
+mod plan;
+mod watch;
 
 use std::fs::File;
 use std::io::{Read, Write};
@@ -8,38 +11,85 @@ use std::process::{Command, Stdio, exit};
 use std::{env, fs};
 
 use clap::Parser;
-use common::enums::{Game, Operation};
-use common::structs::MergedOption;
+use common::structs::{MergedOption, ScanOption};
 use common::{adjust_extension, dir_has_image};
 use config::TomlConfig;
 use regex::Regex;
 
-use crate::options::{Options, merge_options};
+use crate::headless::run_headless;
+use crate::options::{Operation, Options, merge_options, scan_option};
+use crate::plan::print_plan;
+use crate::watch::run_watch;
 
+/// Puts every array that `toml` spreads over several lines back on a single line.
 fn collapse_array(s: String) -> String {
-  let re = Regex::new(r"(?m)^(\s*blur\s*=\s*)\[\s*(?P<inner>(?:\[[^]]*]\s*,?\s*\n?)+)\s*]").unwrap();
-  re.replace_all(&s, |caps: &regex::Captures| {
-    let prefix = &caps[1];
-    let inner_block = &caps["inner"];
-    let inner_re = Regex::new(r"\[\s*([^]]+?)\s*]").unwrap();
-    let arrays: Vec<String> = inner_re
-      .captures_iter(inner_block)
-      .map(|m| {
-        m[1]
-          .lines()
-          .flat_map(|l| l.trim().trim_end_matches(",").split(",").map(str::trim))
-          .filter(|s| !s.is_empty())
-          .collect::<Vec<_>>()
-          .join(", ")
-      })
-      .collect();
-    let joined = arrays.into_iter().map(|a| format!("[{}]", a)).collect::<Vec<_>>().join(", ");
-    format!("{}[{}]", prefix, joined)
-  })
-  .to_string()
+  let re = Regex::new(r"(?m)^[ \t]*[\w-]+[ \t]*=[ \t]*\[").unwrap();
+  let mut out = String::with_capacity(s.len());
+  let mut rest = s.as_str();
+  while let Some(m) = re.find(rest) {
+    let open = m.end() - 1;
+    out.push_str(&rest[..open]);
+    let (flat, len) = flatten_value(&rest[open..]);
+    out.push_str(&flat);
+    rest = &rest[open + len..];
+  }
+  out.push_str(rest);
+  out
+}
+
+/// Flattens the array or inline table at the start of `s`, returning it and the length of `s` it spans.
+fn flatten_value(s: &str) -> (String, usize) {
+  let mut flat = String::new();
+  let mut depth = 0;
+  let mut quote = None;
+  let mut escaped = false;
+  for (i, c) in s.char_indices() {
+    if let Some(q) = quote {
+      flat.push(c);
+      if escaped {
+        escaped = false;
+      } else if c == '\\' && q == '"' {
+        escaped = true;
+      } else if c == q {
+        quote = None;
+      }
+      continue;
+    }
+    match c {
+      '"' | '\'' => {
+        quote = Some(c);
+        flat.push(c);
+      },
+      '[' => {
+        depth += 1;
+        flat.push(c);
+      },
+      '{' => {
+        depth += 1;
+        flat.push_str("{ ");
+      },
+      ']' | '}' => {
+        depth -= 1;
+        flat.truncate(flat.trim_end_matches([',', ' ']).len());
+        flat.push_str(if c == '}' { " }" } else { "]" });
+        if depth == 0 {
+          return (flat, i + 1);
+        }
+      },
+      ',' => flat.push_str(", "),
+      '=' => flat.push_str(" = "),
+      c if c.is_whitespace() => {},
+      c => flat.push(c),
+    }
+  }
+  // unbalanced, leave as is
+  (s.to_string(), s.len())
 }
 
 fn main() {
+  // parse CLI
+  let cli = Options::parse();
+
   // prepare TOML config
   let bin_path = env::current_exe().expect("Could not get current executable path");
   let toml_path = bin_path.with_extension("toml");
@@ -49,15 +99,23 @@ fn main() {
       eprintln!("Failed to read TOML file '{}': {}", toml_path.display(), e);
       exit(1)
     });
-    toml::from_str(&buf).unwrap_or_else(|e| {
-      eprintln!("Failed to parse TOML file '{}': {}", toml_path.display(), e);
-      exit(1);
-    })
+    toml::from_str::<TomlConfig>(&buf)
+      .unwrap_or_else(|e| {
+        eprintln!("Failed to parse TOML file '{}': {}", toml_path.display(), e);
+        exit(1);
+      })
+      .fill_defaults()
+      .unwrap_or_else(|e| {
+        eprintln!("Invalid TOML file '{}': {}", toml_path.display(), e);
+        exit(1);
+      })
+  } else if cli.dry_run {
+    TomlConfig::default()
   } else {
     // create default and write it out
     let default_config = TomlConfig::default();
     let toml_string = toml::to_string(&default_config).unwrap();
-    // collapse 'blur'
+    // collapse arrays
     let toml_content = collapse_array(toml_string);
     File::create(&toml_path).and_then(|mut f| f.write_all(toml_content.as_bytes())).unwrap_or_else(|e| {
       eprintln!("Failed to write default TOML file '{}': {}", toml_path.display(), e);
@@ -67,16 +125,15 @@ fn main() {
   };
 
   // write TOML config for empty / missing key/value
-  let toml_string = toml::to_string_pretty(&config).unwrap(); // very unlikely to error
-  // collapse 'blur'
-  let toml_content = collapse_array(toml_string);
-  if let Err(e) = fs::write(&toml_path, toml_content) {
-    eprintln!("Failed to write TOML file '{}': {}", toml_path.display(), e);
+  if !cli.dry_run {
+    let toml_string = toml::to_string_pretty(&config).unwrap(); // very unlikely to error
+    // collapse arrays
+    let toml_content = collapse_array(toml_string);
+    if let Err(e) = fs::write(&toml_path, toml_content) {
+      eprintln!("Failed to write TOML file '{}': {}", toml_path.display(), e);
+    }
   }
 
-  // parse CLI
-  let cli = Options::parse();
-
   let target_dir = cli.target.clone();
   if !target_dir.exists() {
     eprintln!("Target directory '{}' does not exist", target_dir.display());
@@ -88,118 +145,124 @@ fn main() {
   }
 
   // validate Options
-  if cli.operation != Operation::Full && cli.operation != Operation::CreateDirectory {
-    if cli.game == Game::None {
-      eprintln!(
-        "When Operation {:?} is specified, you must also set Game to something other than 'None'.",
-        cli.operation
-      );
-      exit(1);
-    }
+  let scan = scan_option(&cli, &config);
+  if let Err(e) = scan.validate().and_then(|_| config.output().validate()) {
+    eprintln!("{}", e);
+    exit(1);
   }
+  let game = config.resolve_game(&cli.game).unwrap_or_else(|e| {
+    eprintln!("{}", e);
+    exit(1);
+  });
 
   // handle Operation
-  match cli.operation {
+  match &cli.operation {
     // CreateDirectory
     Operation::CreateDirectory => {
-      for &op in &[
-        Operation::Background,
-        Operation::Center,
-        Operation::Cutscene,
-        Operation::Foreground0,
-        Operation::Foreground1,
-        Operation::Foreground2,
-        Operation::Foreground3,
-        Operation::Foreground4,
-        Operation::Foreground5,
-        Operation::Full,
-      ] {
-        if let Some(folder_name) = config.folder_name(op) {
-          let dir_path = target_dir.join(folder_name);
-          if let Err(e) = fs::create_dir_all(&dir_path) {
-            eprintln!("Failed to create directory '{}': {}", dir_path.display(), e);
-          }
+      for folder_name in config.directories(&game) {
+        let dir_path = target_dir.join(folder_name);
+        if cli.dry_run {
+          println!("Would create directory '{}'", dir_path.display());
+          continue;
         }
+        if let Err(e) = fs::create_dir_all(&dir_path) {
+          eprintln!("Failed to create directory '{}': {}", dir_path.display(), e);
+        }
+      }
+      if !cli.dry_run {
+        println!("Created directory under '{}'.", target_dir.display());
       }
-      println!("Created directory under '{}'.", target_dir.display());
-      return;
     },
     // All
     Operation::All => {
       let mut sub_options = Vec::new();
-      for &op in &[
-        Operation::Background,
-        Operation::Center,
-        Operation::Cutscene,
-        Operation::Foreground0,
-        Operation::Foreground1,
-        Operation::Foreground2,
-        Operation::Foreground3,
-        Operation::Foreground4,
-        Operation::Foreground5,
-        Operation::Full,
-      ] {
-        if let Some(folder_name) = config.folder_name(op) {
-          let sub_target = target_dir.join(folder_name);
-          if !sub_target.exists() {
-            continue;
-          }
-          let has_image = dir_has_image(&sub_target);
-          if !has_image {
-            let _ = fs::remove_dir_all(&sub_target);
-            continue;
+      for layer in config.layers(&game) {
+        let sub_target = target_dir.join(config.folder_name(&game, &layer));
+        if !sub_target.exists() {
+          continue;
+        }
+        // only remove empty directories, not those whose images are filtered out or that hold other files
+        let has_any_image = dir_has_image(&sub_target, &ScanOption { recursive: true, ..Default::default() });
+        if !has_any_image {
+          if !cli.dry_run {
+            let _ = fs::remove_dir(&sub_target);
           }
-
-          let eff = merge_options(&cli, &config, &sub_target, cli.game, op, true);
-          sub_options.push(eff);
+          continue;
         }
+        if !dir_has_image(&sub_target, &scan) {
+          continue;
+        }
+
+        let eff = merge_options(&cli, &config, &sub_target, &game, &layer, true);
+        sub_options.push(eff);
       }
-      for mo in sub_options {
-        run_gui(&mo);
-      }
-      return;
+      run(&cli, &sub_options);
     },
-    // others
-    op @ (Operation::Background
-    | Operation::Center
-    | Operation::Cutscene
-    | Operation::Foreground0
-    | Operation::Foreground1
-    | Operation::Foreground2
-    | Operation::Foreground3
-    | Operation::Foreground4
-    | Operation::Foreground5
-    | Operation::Full) => {
+    // single layer
+    Operation::Layer(name) => {
+      let layer = config.resolve_layer(&game, name).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+      });
+      let folder_name = config.folder_name(&game, &layer);
       let mut new_dir = None;
-      if let Some(folder_name) = config.folder_name(op) {
-        let dir1 = target_dir.join(folder_name);
-        if dir1.exists() && dir_has_image(&dir1) {
-          new_dir = Some(dir1);
-        }
+      let dir1 = target_dir.join(&folder_name);
+      if dir1.exists() && dir_has_image(&dir1, &scan) {
+        new_dir = Some(dir1);
       }
-      if new_dir.is_none() {
-        if dir_has_image(&target_dir) {
-          new_dir = Some(target_dir.clone());
-        }
+      if new_dir.is_none() && dir_has_image(&target_dir, &scan) {
+        new_dir = Some(target_dir.clone());
       }
       let final_target = if let Some(d) = new_dir {
         d
       } else {
-        eprintln!(
-          "No image files were found in '{}' or its '{}' directory.",
-          target_dir.display(),
-          config.folder_name(op).unwrap_or("<unknown>".to_string())
-        );
+        eprintln!("No image files were found in '{}' or its '{}' directory.", target_dir.display(), folder_name);
         exit(1);
       };
 
-      let mo = merge_options(&cli, &config, &final_target, cli.game, op, false);
-      run_gui(&mo);
-      return;
+      let mo = merge_options(&cli, &config, &final_target, &game, &layer, false);
+      run(&cli, &[mo]);
+    },
+    // Watch
+    Operation::Watch => {
+      if cli.dry_run {
+        eprintln!("'watch' cannot be combined with '--dry-run'.");
+        exit(1);
+      }
+      run_watch(&cli, &config, &target_dir, &game);
     },
   }
 }
 
+fn run(cli: &Options, mos: &[MergedOption]) {
+  if cli.dry_run {
+    if !print_plan(mos, cli.json) {
+      exit(1);
+    }
+    return;
+  }
+  if !cli.no_gui {
+    for mo in mos {
+      run_gui(mo);
+    }
+    return;
+  }
+
+  let (mut done, mut skipped, mut failed) = (0, 0, 0);
+  for mo in mos {
+    let summary = run_headless(mo);
+    done += summary.done;
+    skipped += summary.skipped;
+    failed += summary.failed;
+  }
+  if mos.len() > 1 {
+    println!("Total: {} done, {} skipped, {} failed", done, skipped, failed);
+  }
+  if failed > 0 {
+    exit(1);
+  }
+}
+
 fn run_gui(mo: &MergedOption) {
   #[cfg(debug_assertions)]
   println!("DEBUG: Content of MergedOption: {:#?}", mo);
@@ -213,6 +276,8 @@ fn run_gui(mo: &MergedOption) {
     exit(1);
   }
 
+  // 'cs-gui' keeps running on its own after 'cs' exits
+  #[allow(clippy::zombie_processes)]
   let mut child = Command::new(&bin_gui)
     .stdin(Stdio::piped())
     .stdout(Stdio::inherit())
@@ -1,42 +1,147 @@
-use std::env;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, thread};
 
-use clap::Parser;
-use common::enums::{CropPosition, Game, Operation};
-use common::structs::MergedOption;
+use clap::{Parser, ValueEnum};
+use common::enums::{
+  Anchor, ConflictPolicy, CropPosition, OriginalsAction, OutputFormat, RedactStyle, ResizeFilter, ResizeMode, WebPMode,
+};
+use common::structs::{AspectRatio, BlurRegion, Coord, MergedOption, ScanOption};
+use common::template::FilenameTemplate;
 
-use crate::config::TomlConfig;
+use crate::config::{NO_GAME, TomlConfig};
 
 #[derive(Parser)]
 #[command(version, about)]
 pub struct Options {
-  /// Operation to take on to the screenshots.
-  /// If you specify anything other than 'Full' or 'CreateDirectory', you must also set '-g|--game' to other than
-  /// 'None'.
-  #[command(subcommand)]
+  /// Operation to take on to the screenshots: 'all' [alias: a], 'create-directory' [alias: cd], 'watch', or the name
+  /// or alias of a layer of the game. The default game 'none' only has the 'full' layer [alias: f].
+  #[arg(value_name = "OPERATION", index = 1)]
   pub operation: Operation,
 
   /// Target directory (default: current working directory)
-  #[arg(global = true, value_name = "TARGET", default_value = get_cwd().into_os_string(), index = 1)]
+  #[arg(value_name = "TARGET", default_value = get_cwd().into_os_string(), index = 2)]
   pub target: PathBuf,
 
-  /// Manual override: Area for blur, as 'x,y,width,height'
-  #[arg(long, global = true, value_parser = parse_tuple)]
-  pub blur: Option<Vec<[u32; 4]>>,
+  /// Convert images that some blur areas cannot be applied to, as their size or aspect ratio differs from
+  /// '--reference', instead of failing them
+  #[arg(long, global = true)]
+  pub allow_unblurred: bool,
+
+  /// Manual override: Area for blur, as '[anchor:]x,y,width,height[,style[,strength]]'. Coordinates are pixels or
+  /// percentages like '2.5%'. Anchor is one of top-left, top, top-right, left, center, right, bottom-left, bottom and
+  /// bottom-right. Style is one of box, gaussian, pixelate, solid and noise
+  #[arg(long, global = true, value_parser = parse_region)]
+  pub blur: Option<Vec<BlurRegion>>,
+
+  /// Manual override: where the '--crop-aspect' area sits in the cropped image
+  #[arg(long, global = true, value_enum)]
+  pub crop_anchor: Option<Anchor>,
+
+  /// Manual override: keep the largest area of this aspect ratio, like '21:9', within the cropped image
+  #[arg(long, global = true, value_name = "W:H")]
+  pub crop_aspect: Option<AspectRatio>,
 
   /// Manual override: crop height in pixel
   #[arg(long, global = true)]
   pub crop_height: Option<u32>,
 
-  /// Manual override: crop position
-  #[arg(long, global = true, value_enum)]
+  /// Manual override: crop position, one of top, bottom, center, full and auto, a row like '120' to keep the crop
+  /// height from, or an area as 'x,y,width,height' of pixels or percentages like '2.5%'
+  #[arg(long, global = true, value_parser = parse_crop_pos)]
   pub crop_pos: Option<CropPosition>,
 
-  /// Game that the screenshots are taken from
-  #[arg(short = 'g', long, global = true, value_enum, default_value_t = Game::None)]
-  pub game: Game,
+  /// Print what would be done to every image instead of converting. Nothing is written, not even cs.toml
+  #[arg(long, global = true)]
+  pub dry_run: bool,
+
+  /// Manual override: output format
+  #[arg(long, global = true, value_enum)]
+  pub format: Option<OutputFormat>,
+
+  /// Manual override: output file name without extension. Placeholders are {game}, {layer}, {stem}, {index}, {width},
+  /// {height}, {mtime}, {date} and {time}, e.g. '{game}-{layer}-{index:03}-{stem}' or '{date:%Y%m%d}_{stem}'
+  #[arg(long, global = true, value_name = "TEMPLATE", value_parser = parse_template)]
+  pub filename: Option<String>,
+
+  /// Manual override: only convert images matching this glob. Can be repeated
+  #[arg(long, global = true, value_name = "GLOB")]
+  pub include: Option<Vec<String>>,
+
+  /// Manual override: skip images and directories matching this glob. Can be repeated
+  #[arg(long, global = true, value_name = "GLOB")]
+  pub exclude: Option<Vec<String>>,
+
+  /// Game that the screenshots are taken from: 'none', or the name or alias of a '[game.<name>]' table in cs.toml
+  #[arg(short = 'g', long, global = true, default_value = "none")]
+  pub game: String,
 
-  /// Manual override: Width of original image
+  /// Skip images converted by an earlier incremental run, unless the image or the settings changed since
+  #[arg(short = 'i', long, global = true)]
+  pub incremental: bool,
+
+  /// Manual override: what to do when an output file already exists, or two images would be written to the same file
+  #[arg(long, global = true, value_enum)]
+  pub on_conflict: Option<ConflictPolicy>,
+
+  /// Manual override: what happens to every image once its output is written and verified
+  #[arg(long, global = true, value_enum)]
+  pub originals: Option<OriginalsAction>,
+
+  /// Number of images converted at the same time (default: number of CPU cores)
+  #[arg(short = 'j', long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+  pub jobs: Option<u32>,
+
+  /// Print the '--dry-run' plan as JSON
+  #[arg(long, global = true, requires = "dry_run")]
+  pub json: bool,
+
+  /// Manual override: WebP compression method, from 0 (fast) to 6 (slower-better)
+  #[arg(long, global = true, value_parser = clap::value_parser!(i32).range(0..=6))]
+  pub method: Option<i32>,
+
+  /// Manual override: WebP near-lossless level, from 0 (most lossy) to 100 (off). Implies '--webp-mode near-lossless'
+  #[arg(long, global = true, value_parser = clap::value_parser!(i32).range(0..=100))]
+  pub near_lossless: Option<i32>,
+
+  /// Recreate the subdirectory tree of the target directory under the output directory
+  #[arg(long, global = true)]
+  pub mirror: bool,
+
+  /// Convert in this process and print the progress to the terminal instead of spawning 'cs-gui'
+  #[arg(long, global = true)]
+  pub no_gui: bool,
+
+  /// Manual override: WebP quality, from 0 (smallest) to 100 (largest)
+  #[arg(long, global = true, value_parser = parse_quality)]
+  pub quality: Option<f32>,
+
+  /// Manual override: Resolution that the blur areas and crop height are given in, as 'width,height'
+  #[arg(long, global = true, value_parser = parse_pair)]
+  pub reference: Option<[u32; 2]>,
+
+  /// Manual override: resize mode, one of 'fit-width:<px>', 'fit-height:<px>', 'fit-box:<width>x<height>',
+  /// 'scale:<factor>' and 'max-megapixels:<n>'. Replaces '--width-to'
+  #[arg(long, global = true, value_name = "MODE", value_parser = parse_resize)]
+  pub resize: Option<ResizeMode>,
+
+  /// Manual override: resampling filter used to resize
+  #[arg(long, global = true, value_enum, value_name = "FILTER")]
+  pub resize_filter: Option<ResizeFilter>,
+
+  /// Search subdirectories of the target directory too
+  #[arg(short = 'r', long, global = true)]
+  pub recursive: bool,
+
+  /// Manual override: write no ICC profile or EXIF into the output, whatever '[output.<format>.metadata]' says
+  #[arg(long, global = true)]
+  pub strip_metadata: bool,
+
+  /// Manual override: WebP encoding mode
+  #[arg(long, global = true, value_enum)]
+  pub webp_mode: Option<WebPMode>,
+
+  /// Manual override: Only convert images of this width, 0 for any width
   #[arg(long, global = true)]
   pub width_from: Option<u32>,
 
@@ -45,6 +150,34 @@ pub struct Options {
   pub width_to: Option<u32>,
 }
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Operation {
+  /// Process every layer
+  All,
+  /// Create the folder of every layer
+  CreateDirectory,
+  /// Process a single layer, by its name or alias
+  Layer(String),
+  /// Convert images as they are written into the folder of any layer, until interrupted
+  Watch,
+}
+
+/// Operation names and aliases taken before layer names, so no layer can be named or aliased like them
+pub const RESERVED_OPERATIONS: [&str; 5] = ["all", "a", "create-directory", "cd", "watch"];
+
+impl FromStr for Operation {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(match s {
+      "all" | "a" => Operation::All,
+      "create-directory" | "cd" => Operation::CreateDirectory,
+      "watch" => Operation::Watch,
+      _ => Operation::Layer(s.to_string()),
+    })
+  }
+}
+
 fn get_cwd() -> PathBuf {
   env::current_dir().unwrap()
 }
@@ -53,52 +186,292 @@ pub fn merge_options(
   opt: &Options,
   config: &TomlConfig,
   target: &Path,
-  game: Game,
-  op: Operation,
+  game: &str,
+  layer: &str,
   save_at_parent: bool,
 ) -> MergedOption {
+  // auto_crop_threshold
+  let auto_crop_threshold = config.auto_crop_threshold(game, layer);
+
   // blur
-  let blur = opt.blur.clone().unwrap_or_else(|| config.blur(game, op));
+  let blur = opt.blur.clone().unwrap_or_else(|| config.blur(game, layer));
+
+  // crop_anchor, crop_aspect
+  let crop_anchor = opt.crop_anchor.or_else(|| config.crop_anchor(game, layer)).unwrap_or(Anchor::Center);
+  let crop_aspect = opt.crop_aspect.or_else(|| config.crop_aspect(game, layer));
 
   // crop_height
-  let crop_height = match op {
-    Operation::All | Operation::CreateDirectory | Operation::Full => 0,
-    _ => opt
-      .crop_height
-      .or_else(|| Some(config.crop_height(game, op)))
-      .expect("'crop_height' must come from either CLI or TOML."),
-  };
+  let crop_height = opt.crop_height.unwrap_or_else(|| config.crop_height(game, layer));
 
   // crop_pos
-  let crop_pos = opt.crop_pos.unwrap_or_else(|| config.crop_position(game, op));
+  let crop_pos = opt.crop_pos.unwrap_or_else(|| config.crop_position(game, layer));
+
+  // jobs
+  let jobs = opt.jobs.map(|j| j as usize).unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+
+  // originals
+  let originals = opt.originals.or_else(|| config.originals(game, layer)).unwrap_or_default();
+
+  // output
+  let mut output = config.output().clone();
+  if let Some(format) = opt.format.or_else(|| config.format(game, layer)) {
+    output.format = format;
+  }
+  if let Some(filename) = &opt.filename {
+    output.filename = filename.clone();
+  }
+  if let Some(policy) = opt.on_conflict {
+    output.on_conflict = policy;
+  }
+  if let Some(quality) = opt.quality {
+    output.webp.quality = quality;
+  }
+  if let Some(method) = opt.method {
+    output.webp.method = method;
+  }
+  if let Some(level) = opt.near_lossless {
+    output.webp.near_lossless = level;
+  }
+  if opt.strip_metadata {
+    output.strip_metadata();
+  }
+  let webp_mode =
+    opt.webp_mode.or(opt.near_lossless.map(|_| WebPMode::NearLossless)).or_else(|| config.webp_mode(game, layer));
+  if let Some(mode) = webp_mode {
+    output.webp.set_mode(mode);
+  }
+
+  // reference
+  let reference = opt.reference.unwrap_or_else(|| config.reference(game));
+
+  // resize, resize_filter
+  // '--width-to' overrides the mode of the layer too
+  let resize = opt.resize.or_else(|| if opt.width_to.is_some() { None } else { config.resize(game, layer) });
+  let resize_filter = opt.resize_filter.or_else(|| config.resize_filter(game, layer)).unwrap_or_default();
 
   // width_from, width_to
-  let (default_width_from, default_width_to) = if game != Game::None { (1920, 1280) } else { (0, 0) };
-  let width_from = opt.width_from.unwrap_or(default_width_from);
-  let width_to = opt.width_to.unwrap_or(default_width_to);
+  let width_from = opt.width_from.or_else(|| config.width_from(game, layer)).unwrap_or(0);
+  let width_to =
+    opt.width_to.or_else(|| config.width_to(game, layer)).unwrap_or(if game != NO_GAME { 1280 } else { 0 });
 
   MergedOption {
+    allow_unblurred: opt.allow_unblurred,
+    auto_crop_threshold,
     blur,
+    crop_anchor,
+    crop_aspect,
     crop_height,
     crop_pos,
-    game,
+    game: game.to_string(),
+    incremental: opt.incremental,
+    jobs,
+    layer: layer.to_string(),
+    originals,
+    output,
+    reference,
+    resize,
+    resize_filter,
     save_at_parent,
-    operation: op,
+    scan: scan_option(opt, config),
     target: target.to_path_buf(),
     width_from,
     width_to,
   }
 }
 
-pub fn parse_tuple(s: &str) -> Result<[u32; 4], String> {
+pub fn scan_option(opt: &Options, config: &TomlConfig) -> ScanOption {
+  let mut scan = config.scan().clone();
+  scan.recursive |= opt.recursive;
+  scan.mirror |= opt.mirror;
+  if let Some(include) = &opt.include {
+    scan.include = include.clone();
+  }
+  if let Some(exclude) = &opt.exclude {
+    scan.exclude = exclude.clone();
+  }
+  scan
+}
+
+pub fn parse_tuple(s: &str) -> Result<[Coord; 4], String> {
   let parts: Vec<&str> = s.split(',').collect();
   if parts.len() != 4 {
-    return Err("Must be four unsigned integers separated by commas, e.g. \"10,20,31,42\"".into());
+    return Err("Must be four unsigned integers or percentages separated by commas, e.g. \"10,20,31,42\"".into());
+  }
+  let mut coords = [Coord::Px(0); 4];
+  for (i, part) in parts.iter().enumerate() {
+    coords[i] = part.parse::<Coord>().map_err(|e| format!("Failed to parse position {}: {}", i + 1, e))?;
+  }
+  Ok(coords)
+}
+
+/// Parses 'top', 'bottom', 'center', 'full', 'auto', 'y' or 'x,y,width,height'.
+pub fn parse_crop_pos(s: &str) -> Result<CropPosition, String> {
+  match s.trim() {
+    "top" => Ok(CropPosition::Top),
+    "bottom" => Ok(CropPosition::Bottom),
+    "center" => Ok(CropPosition::Center),
+    "full" => Ok(CropPosition::Full),
+    "auto" => Ok(CropPosition::Auto),
+    s if s.contains(',') => parse_tuple(s).map(CropPosition::Rect),
+    s => s.parse::<u32>().map(|y| CropPosition::Custom { y }).map_err(|_| {
+      "Must be top, bottom, center, full, auto, a row like \"120\" or an area like \"660,300,600,480\"".to_string()
+    }),
+  }
+}
+
+/// Parses '<mode>:<value>', such as 'fit-width:1280' or 'fit-box:1280x720'.
+pub fn parse_resize(s: &str) -> Result<ResizeMode, String> {
+  let usage = "Must be 'fit-width:<px>', 'fit-height:<px>', 'fit-box:<width>x<height>', 'scale:<factor>' (at most 4) \
+               or 'max-megapixels:<n>', e.g. \"fit-box:1280x720\"";
+  let (mode, value) = s.split_once(':').ok_or(usage)?;
+  let px = |v: &str| v.trim().parse::<u32>().ok().filter(|&v| v > 0);
+  let factor = |v: &str| v.trim().parse::<f64>().ok().filter(|&v| v > 0.0 && v.is_finite());
+  let mode = match mode.trim() {
+    "fit-width" => px(value).map(ResizeMode::FitWidth),
+    "fit-height" => px(value).map(ResizeMode::FitHeight),
+    "fit-box" => value.split_once('x').and_then(|(w, h)| Some(ResizeMode::FitBox([px(w)?, px(h)?]))),
+    "scale" => factor(value).filter(|&f| f <= ResizeMode::MAX_SCALE).map(ResizeMode::Scale),
+    "max-megapixels" => factor(value).map(ResizeMode::MaxMegapixels),
+    _ => None,
+  };
+  mode.ok_or_else(|| usage.to_string())
+}
+
+pub fn parse_template(s: &str) -> Result<String, String> {
+  FilenameTemplate::parse(s).map(|_| s.to_string())
+}
+
+/// Parses '[anchor:]x,y,width,height[,style[,strength]]'.
+pub fn parse_region(s: &str) -> Result<BlurRegion, String> {
+  let (anchor, s) = match s.split_once(':') {
+    Some((anchor, rest)) => {
+      (<Anchor as ValueEnum>::from_str(anchor.trim(), true).map_err(|_| format!("Unknown anchor: '{}'", anchor))?, rest)
+    },
+    None => (Anchor::TopLeft, s),
+  };
+  let parts: Vec<&str> = s.splitn(6, ',').collect();
+  if parts.len() < 4 {
+    return Err(
+      "Must be '[anchor:]x,y,width,height[,style[,strength]]', e.g. \"bottom-right:10,20,5%,42,pixelate,16\"".into(),
+    );
   }
-  let mut nums = [0u32; 4];
+  let mut region = BlurRegion::from(parse_tuple(&parts[..4].join(","))?);
+  region.anchor = anchor;
+  if let Some(style) = parts.get(4) {
+    region.style =
+      <RedactStyle as ValueEnum>::from_str(style.trim(), true).map_err(|_| format!("Unknown style: '{}'", style))?;
+  }
+  if let Some(strength) = parts.get(5) {
+    region.strength =
+      strength.trim().parse::<u32>().map_err(|_| format!("Failed to parse strength: '{}'", strength))?;
+  }
+  Ok(region)
+}
+
+/// Parses a WebP quality from 0 to 100, which may have a fraction.
+pub fn parse_quality(s: &str) -> Result<f32, String> {
+  match s.trim().parse::<f32>() {
+    Ok(q) if (0.0..=100.0).contains(&q) => Ok(q),
+    _ => Err("Must be a number from 0 to 100, e.g. \"85\" or \"92.5\"".into()),
+  }
+}
+
+pub fn parse_pair(s: &str) -> Result<[u32; 2], String> {
+  let parts: Vec<&str> = s.split(',').collect();
+  if parts.len() != 2 {
+    return Err("Must be two unsigned integers separated by a comma, e.g. \"1920,1080\"".into());
+  }
+  let mut nums = [0u32; 2];
   for (i, part) in parts.iter().enumerate() {
     nums[i] =
       part.trim().parse::<u32>().map_err(|_| format!("Failed to parse integer at position {}: '{}'", i + 1, part))?;
   }
   Ok(nums)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn region_defaults_to_top_left_box() {
+    let region = parse_region("10,20,30,40").unwrap();
+    assert_eq!(region, BlurRegion::from([10, 20, 30, 40]));
+    assert_eq!(region.anchor, Anchor::TopLeft);
+    assert_eq!(region.style, RedactStyle::Box);
+  }
+
+  #[test]
+  fn region_takes_anchor_percent_style_and_strength() {
+    let region = parse_region(" bottom-right : 10, 20,5%,42.5% ,mosaic, 16").unwrap();
+    assert_eq!(region.area, [Coord::Px(10), Coord::Px(20), Coord::Percent(5.0), Coord::Percent(42.5)]);
+    assert_eq!(region.anchor, Anchor::BottomRight);
+    assert_eq!(region.style, RedactStyle::Pixelate);
+    assert_eq!(region.strength, 16);
+    assert_eq!(parse_region("center:0%,0%,100%,100%,solid").unwrap().style, RedactStyle::Solid);
+  }
+
+  #[test]
+  fn region_rejects_malformed_input() {
+    for (s, err) in [
+      ("10,20,30", "Must be"),
+      ("middle:10,20,30,40", "Unknown anchor"),
+      ("10,20,-30,40", "position 3"),
+      ("10,20,30,101%", "from 0% to 100%"),
+      ("10,20,30,40,smear", "Unknown style"),
+      ("10,20,30,40,box,strong", "strength"),
+      ("10,20,30,40,box,8,9", "strength"),
+    ] {
+      let e = parse_region(s).unwrap_err();
+      assert!(e.contains(err), "{}: {}", s, e);
+    }
+  }
+
+  #[test]
+  fn crop_pos_takes_names_rows_and_areas() {
+    assert_eq!(parse_crop_pos(" bottom "), Ok(CropPosition::Bottom));
+    assert_eq!(parse_crop_pos("auto"), Ok(CropPosition::Auto));
+    assert_eq!(parse_crop_pos("120"), Ok(CropPosition::Custom { y: 120 }));
+    assert_eq!(
+      parse_crop_pos("660,300,50%,25%"),
+      Ok(CropPosition::Rect([Coord::Px(660), Coord::Px(300), Coord::Percent(50.0), Coord::Percent(25.0)]))
+    );
+  }
+
+  #[test]
+  fn crop_pos_rejects_malformed_input() {
+    for (s, err) in [("middle", "Must be"), ("-5", "Must be"), ("10,20,30", "four"), ("10,20,30,x", "position 4")] {
+      let e = parse_crop_pos(s).unwrap_err();
+      assert!(e.contains(err), "{}: {}", s, e);
+    }
+  }
+
+  #[test]
+  fn resize_takes_every_mode() {
+    assert_eq!(parse_resize("fit-width:1280"), Ok(ResizeMode::FitWidth(1280)));
+    assert_eq!(parse_resize("fit-height: 720"), Ok(ResizeMode::FitHeight(720)));
+    assert_eq!(parse_resize("fit-box:1280x720"), Ok(ResizeMode::FitBox([1280, 720])));
+    assert_eq!(parse_resize("scale:0.5"), Ok(ResizeMode::Scale(0.5)));
+    assert_eq!(parse_resize("scale:4"), Ok(ResizeMode::Scale(4.0)));
+    assert_eq!(parse_resize("max-megapixels:2"), Ok(ResizeMode::MaxMegapixels(2.0)));
+  }
+
+  #[test]
+  fn resize_rejects_malformed_input() {
+    for s in [
+      "1280",
+      "fit-width:0",
+      "fit-height:-720",
+      "fit-box:1280",
+      "fit-box:1280x0",
+      "scale:0",
+      "scale:4.5",
+      "scale:inf",
+      "scale:NaN",
+      "max-megapixels:-1",
+      "stretch:2",
+    ] {
+      assert!(parse_resize(s).is_err(), "{}", s);
+    }
+  }
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use common::manifest::Manifest;
+use common::pipeline::{Claim, ImagePlan, Run, claim_outputs, plan_image};
+use common::structs::MergedOption;
+use common::{dir_collect_image, output_dir};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct LayerPlan<'a> {
+  options: &'a MergedOption,
+  output_dir: PathBuf,
+  images: Vec<ImageEntry>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ImageEntry {
+  Plan(ImagePlan),
+  Skipped { input: PathBuf, skipped: String },
+  Error { input: PathBuf, error: String },
+}
+
+/// Prints what converting every image of `mos` would do, without writing anything.
+/// Returns `false` if any image would fail.
+pub fn print_plan(mos: &[MergedOption], json: bool) -> bool {
+  let layers: Vec<LayerPlan> = mos
+    .iter()
+    .map(|mo| {
+      let to = output_dir(mo);
+      let imgs = dir_collect_image(&mo.target, &mo.scan);
+      let manifest = mo.incremental.then(|| Manifest::load(mo, &to));
+      let claims = claim_outputs(&imgs, mo, &to, manifest.as_ref(), &Run::default());
+      let images = imgs
+        .into_iter()
+        .zip(claims)
+        .map(|(f, claim)| match claim {
+          Claim::Write { dst, conflict } => match plan_image(&f, mo, &dst) {
+            Ok(plan) => ImageEntry::Plan(ImagePlan { conflict, ..plan }),
+            Err(error) => ImageEntry::Error { input: f, error },
+          },
+          Claim::Skip(skipped) => ImageEntry::Skipped { input: f, skipped },
+          Claim::Fail(error) => ImageEntry::Error { input: f, error },
+        })
+        .collect();
+      LayerPlan { options: mo, output_dir: to, images }
+    })
+    .collect();
+  let ok = layers.iter().flat_map(|l| &l.images).all(|i| !matches!(i, ImageEntry::Error { .. }));
+
+  if json {
+    println!("{}", serde_json::to_string_pretty(&layers).unwrap());
+    return ok;
+  }
+
+  for layer in &layers {
+    let mo = layer.options;
+    println!("Files From: {}", mo.target.display());
+    println!("Saved To:   {}", layer.output_dir.display());
+    println!("Game: {} | Layer: {}", mo.game, mo.layer);
+    println!("Options: {:#?}", mo);
+    let total = layer.images.len();
+    for (i, entry) in layer.images.iter().enumerate() {
+      match entry {
+        ImageEntry::Plan(plan) => print_image(i + 1, total, plan),
+        ImageEntry::Skipped { input, skipped } => {
+          println!("[{}/{}] {}\n  ↷ {}", i + 1, total, input.display(), skipped)
+        },
+        ImageEntry::Error { input, error } => println!("[{}/{}] {}\n  ✖ {}", i + 1, total, input.display(), error),
+      }
+    }
+    println!();
+  }
+  ok
+}
+
+fn print_image(current: usize, total: usize, plan: &ImagePlan) {
+  println!("[{}/{}] {} ({}x{})", current, total, plan.input.display(), plan.width, plan.height);
+  if plan.blur.is_empty() {
+    println!("  blur:   none");
+  }
+  for r in &plan.blur {
+    let [x, y, w, h] = r.area;
+    println!("  blur:   {:?} {} at {},{} {}x{}", r.style, r.strength, x, y, w, h);
+  }
+  if let Some(unblurred) = &plan.unblurred {
+    println!("  ⚠ {}", unblurred);
+  }
+  let [x, y, w, h] = plan.crop;
+  println!("  crop:   {},{} {}x{}", x, y, w, h);
+  match plan.resize {
+    Some([w, h]) => println!("  resize: {}x{}", w, h),
+    None => println!("  resize: none"),
+  }
+  println!("  output: {}", plan.output.display());
+  if let Some(conflict) = &plan.conflict {
+    println!("  ⚠ {}", conflict);
+  }
+}
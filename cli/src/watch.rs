@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::Local;
+use common::pipeline::{ImageMsg, Run, process_image};
+use common::report::RunReport;
+use common::structs::MergedOption;
+use common::{dir_collect_image, output_dir};
+use notify::event::{AccessKind, AccessMode, CreateKind, ModifyKind};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::config::TomlConfig;
+use crate::options::{Options, merge_options};
+
+/// How long a new file must stay the same size before it is taken as written, unless its writer closed it
+const SETTLE: Duration = Duration::from_secs(1);
+/// How often pending files are checked
+const TICK: Duration = Duration::from_millis(250);
+
+/// A file seen by the watcher that may still be being written.
+struct Pending {
+  size: u64,
+  modified: Option<SystemTime>,
+  changed: Instant,
+  /// The writer closed the file, which only inotify reports
+  closed: bool,
+}
+
+/// A folder whose images are converted, with the `{index}` its next image gets and the report of its batches so far.
+struct Watched {
+  mo: MergedOption,
+  next_index: usize,
+  report: Option<RunReport>,
+}
+
+/// Layer of images written into the target itself, as `cs full` would pick them up
+const TARGET_LAYER: &str = "full";
+
+/// Watches the layer folders of `game` under `target` and converts every image written into them, until interrupted.
+/// Images written into `target` itself are converted with the [`TARGET_LAYER`] layer.
+pub fn run_watch(cli: &Options, config: &TomlConfig, target: &Path, game: &str) {
+  let target = target.canonicalize().unwrap_or_else(|_| target.to_path_buf());
+  let (tx, rx) = mpsc::channel();
+  let mut watcher = notify::recommended_watcher(tx).unwrap_or_else(|e| {
+    eprintln!("Failed to start watching: {}", e);
+    exit(1);
+  });
+  // the target itself is only watched for layer folders created later and images written into it
+  if let Err(e) = watcher.watch(&target, RecursiveMode::NonRecursive) {
+    eprintln!("Failed to watch '{}': {}", target.display(), e);
+    exit(1);
+  }
+
+  let folders: HashMap<String, String> =
+    config.layers(game).into_iter().map(|layer| (config.folder_name(game, &layer), layer)).collect();
+  let mut root = folders.values().any(|layer| layer == TARGET_LAYER).then(|| {
+    let mut mo = merge_options(cli, config, &target, game, TARGET_LAYER, false);
+    // layer folders have their own options
+    mo.scan.recursive = false;
+    Watched { mo, next_index: 1, report: None }
+  });
+  let mut layers = BTreeMap::new();
+  for (folder, layer) in &folders {
+    let dir = target.join(folder);
+    if dir.is_dir() {
+      watch_layer(&mut watcher, &mut layers, merge_options(cli, config, &dir, game, layer, true));
+    }
+  }
+  if let Some(root) = &root {
+    println!("{} ▶ Layer '{}' in '{}'", now(), root.mo.layer, target.display());
+  }
+  println!("Watching '{}' for new screenshots. Press Ctrl+C to stop.", target.display());
+
+  let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+  // outputs written into the target, which are not screenshots to convert, by when an event last reported them
+  let mut written: HashMap<PathBuf, Instant> = HashMap::new();
+  let mut last_check = Instant::now();
+  loop {
+    match rx.recv_timeout(TICK) {
+      Ok(Ok(event)) => {
+        let closed = matches!(event.kind, EventKind::Access(AccessKind::Close(AccessMode::Write)));
+        let created_dir = matches!(event.kind, EventKind::Create(CreateKind::Folder | CreateKind::Any));
+        for path in event_paths(event) {
+          if created_dir && path.is_dir() && path.parent() == Some(target.as_path()) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            if let Some(layer) = folders.get(&name) {
+              let mo = merge_options(cli, config, &path, game, layer, true);
+              // a folder moved in may hold images already
+              for f in dir_collect_image(&path, &mo.scan) {
+                pending.entry(f).or_insert_with(Pending::new);
+              }
+              watch_layer(&mut watcher, &mut layers, mo);
+            }
+          } else if let Some(seen) = written.get_mut(&path) {
+            *seen = Instant::now();
+          } else if path.is_file()
+            && (layers.keys().any(|dir| path.starts_with(dir))
+              || (root.is_some() && path.parent() == Some(target.as_path())))
+          {
+            let entry = pending.entry(path).or_insert_with(Pending::new);
+            entry.closed |= closed;
+          }
+        }
+      },
+      Ok(Err(e)) => eprintln!("{} ⚠ {}", now(), e),
+      Err(RecvTimeoutError::Timeout) => {},
+      Err(RecvTimeoutError::Disconnected) => {
+        eprintln!("Watcher stopped unexpectedly");
+        exit(1);
+      },
+    }
+
+    if last_check.elapsed() >= TICK {
+      last_check = Instant::now();
+      // the events of an output all arrive right after its batch
+      written.retain(|_, seen| seen.elapsed() < SETTLE);
+      let ready = take_ready(&mut pending);
+      if !ready.is_empty() {
+        convert(&mut layers, root.as_mut(), &ready, &mut written);
+      }
+    }
+  }
+}
+
+fn watch_layer(watcher: &mut RecommendedWatcher, layers: &mut BTreeMap<PathBuf, Watched>, mut mo: MergedOption) {
+  let dir = mo.target.canonicalize().unwrap_or_else(|_| mo.target.clone());
+  // events and `ready` carry canonical paths
+  mo.target = dir.clone();
+  let mode = if mo.scan.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+  match watcher.watch(&dir, mode) {
+    Ok(()) => {
+      println!("{} ▶ Layer '{}' in '{}'", now(), mo.layer, dir.display());
+      layers.insert(dir, Watched { mo, next_index: 1, report: None });
+    },
+    Err(e) => eprintln!("{} ⚠ Failed to watch '{}': {}", now(), dir.display(), e),
+  }
+}
+
+/// Paths an event may have written to. A rename lists the old path first.
+fn event_paths(event: Event) -> Vec<PathBuf> {
+  match event.kind {
+    EventKind::Remove(_)
+    | EventKind::Access(AccessKind::Open(_) | AccessKind::Read | AccessKind::Close(AccessMode::Read)) => Vec::new(),
+    EventKind::Modify(ModifyKind::Name(_)) => event.paths.into_iter().last().into_iter().collect(),
+    _ => event.paths,
+  }
+}
+
+/// Removes and returns the pending files that are done being written.
+fn take_ready(pending: &mut HashMap<PathBuf, Pending>) -> Vec<PathBuf> {
+  let mut ready = Vec::new();
+  pending.retain(|path, entry| {
+    let Ok(meta) = fs::metadata(path) else {
+      // deleted or renamed again before it settled
+      return false;
+    };
+    let modified = meta.modified().ok();
+    if meta.len() != entry.size || modified != entry.modified {
+      *entry = Pending { size: meta.len(), modified, changed: Instant::now(), closed: entry.closed };
+      return true;
+    }
+    let settled = entry.closed || entry.changed.elapsed() >= SETTLE;
+    // a file still open for writing cannot be opened on Windows
+    if meta.len() > 0 && settled && fs::File::open(path).is_ok() {
+      ready.push(path.clone());
+      return false;
+    }
+    true
+  });
+  ready
+}
+
+/// Converts every file of `ready` that its layer would pick up, in one run per layer that counts `{index}` on from the
+/// previous one. Outputs are added to `written`, and every batch to the report of its layer, which is saved again.
+fn convert(
+  layers: &mut BTreeMap<PathBuf, Watched>,
+  root: Option<&mut Watched>,
+  ready: &[PathBuf],
+  written: &mut HashMap<PathBuf, Instant>,
+) {
+  let ready: Vec<PathBuf> = ready.iter().filter_map(|f| f.canonicalize().ok()).collect();
+  let started = Local::now();
+  for watched in layers.values_mut().chain(root) {
+    let mo = &watched.mo;
+    let imgs: Vec<PathBuf> = dir_collect_image(&mo.target, &mo.scan)
+      .into_iter()
+      .filter(|f| ready.contains(f) && !written.contains_key(f))
+      .collect();
+    if imgs.is_empty() {
+      continue;
+    }
+    let run = Run { started, first_index: watched.next_index, batch: true };
+    watched.next_index += imgs.len();
+    let out_dir = output_dir(mo);
+    let report = process_image(imgs, mo, out_dir.clone(), run, &|msg| match msg {
+      ImageMsg::Done { filename } => println!("{} ✔ {}/{} DONE", now(), mo.layer, filename),
+      ImageMsg::Error { text } => eprintln!("{} ✖ {}", now(), text),
+      ImageMsg::Skipped { filename } => println!("{} ↷ {}/{} SKIPPED", now(), mo.layer, filename),
+      ImageMsg::Warning { text } => eprintln!("{} ⚠ {}", now(), text),
+      ImageMsg::Progress { .. } | ImageMsg::Finished => {},
+    });
+    let seen = Instant::now();
+    written.extend(report.images.iter().filter_map(|image| Some((image.output.clone()?, seen))));
+    let report = match watched.report.take() {
+      Some(mut session) => {
+        session.append(report);
+        session
+      },
+      None => report,
+    };
+    if let Err(e) = report.save(&out_dir) {
+      eprintln!("{} ⚠ {}", now(), e);
+    }
+    watched.report = Some(report);
+  }
+}
+
+fn now() -> String {
+  Local::now().format("%H:%M:%S").to_string()
+}
+
+impl Pending {
+  fn new() -> Self {
+    Pending { size: u64::MAX, modified: None, changed: Instant::now(), closed: false }
+  }
+}
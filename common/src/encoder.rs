@@ -0,0 +1,375 @@
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::qoi::QoiEncoder;
+use image::{DynamicImage, ImageEncoder};
+use libwebp_sys::WebPImageHint::{WEBP_HINT_DEFAULT, WEBP_HINT_GRAPH, WEBP_HINT_PHOTO, WEBP_HINT_PICTURE};
+use webp::{Encoder, WebPConfig};
+
+use crate::enums::{OutputFormat, PngCompression, PngFilter, WebPImageHint};
+use crate::metadata::Metadata;
+use crate::structs::{AvifOption, JpegOption, MetadataOption, OutputOption, PngOption, WebPOption};
+
+/// Encoder of a single output format.
+pub trait OutputEncoder {
+  /// File extension of the encoded image, without the leading dot
+  fn extension(&self) -> &'static str;
+
+  /// Metadata carried into the output, or `None` if the format cannot carry any
+  fn metadata(&self) -> Option<&MetadataOption> {
+    None
+  }
+
+  /// Encodes `img` with `metadata` into the content of the output file.
+  fn encode(&self, img: &DynamicImage, metadata: &Metadata) -> Result<Vec<u8>, String>;
+}
+
+impl OutputOption {
+  /// Returns the encoder of the selected `format`, configured with its settings block.
+  pub fn encoder(&self) -> &dyn OutputEncoder {
+    match self.format {
+      OutputFormat::WebP => &self.webp,
+      OutputFormat::Png => &self.png,
+      OutputFormat::Jpeg => &self.jpeg,
+      OutputFormat::Avif => &self.avif,
+      OutputFormat::Qoi => &QoiOption,
+    }
+  }
+}
+
+impl OutputEncoder for WebPOption {
+  fn extension(&self) -> &'static str {
+    "webp"
+  }
+
+  fn metadata(&self) -> Option<&MetadataOption> {
+    Some(&self.metadata)
+  }
+
+  fn encode(&self, img: &DynamicImage, metadata: &Metadata) -> Result<Vec<u8>, String> {
+    let webp = Encoder::from_image(img)
+      .map_err(|e| e.to_string())?
+      .encode_advanced(&webp_config(self))
+      .map_err(|e| format!("{:?}", e))?;
+    if metadata.icc.is_none() && metadata.exif.is_none() {
+      return Ok(webp.to_vec());
+    }
+    mux_webp(&webp, img.width(), img.height(), metadata)
+  }
+}
+
+impl OutputEncoder for PngOption {
+  fn extension(&self) -> &'static str {
+    "png"
+  }
+
+  fn metadata(&self) -> Option<&MetadataOption> {
+    Some(&self.metadata)
+  }
+
+  fn encode(&self, img: &DynamicImage, metadata: &Metadata) -> Result<Vec<u8>, String> {
+    let compression = match self.compression {
+      PngCompression::Default => CompressionType::Default,
+      PngCompression::Fast => CompressionType::Fast,
+      PngCompression::Best => CompressionType::Best,
+      PngCompression::Uncompressed => CompressionType::Uncompressed,
+    };
+    let filter = match self.filter {
+      PngFilter::Adaptive => FilterType::Adaptive,
+      PngFilter::None => FilterType::NoFilter,
+      PngFilter::Sub => FilterType::Sub,
+      PngFilter::Up => FilterType::Up,
+      PngFilter::Avg => FilterType::Avg,
+      PngFilter::Paeth => FilterType::Paeth,
+    };
+    let mut buf = Vec::new();
+    write_with(img, PngEncoder::new_with_quality(&mut buf, compression, filter), metadata)?;
+    Ok(buf)
+  }
+}
+
+impl OutputEncoder for JpegOption {
+  fn extension(&self) -> &'static str {
+    "jpg"
+  }
+
+  fn metadata(&self) -> Option<&MetadataOption> {
+    Some(&self.metadata)
+  }
+
+  fn encode(&self, img: &DynamicImage, metadata: &Metadata) -> Result<Vec<u8>, String> {
+    // JPEG has no alpha channel
+    let rgb = DynamicImage::ImageRgb8(img.to_rgb8());
+    let mut buf = Vec::new();
+    write_with(&rgb, JpegEncoder::new_with_quality(&mut buf, self.quality), metadata)?;
+    Ok(buf)
+  }
+}
+
+impl OutputEncoder for AvifOption {
+  fn extension(&self) -> &'static str {
+    "avif"
+  }
+
+  fn metadata(&self) -> Option<&MetadataOption> {
+    Some(&self.metadata)
+  }
+
+  fn encode(&self, img: &DynamicImage, metadata: &Metadata) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    write_with(img, AvifEncoder::new_with_speed_quality(&mut buf, self.speed, self.quality), metadata)?;
+    Ok(buf)
+  }
+}
+
+/// QOI has no settings
+struct QoiOption;
+
+impl OutputEncoder for QoiOption {
+  fn extension(&self) -> &'static str {
+    "qoi"
+  }
+
+  fn encode(&self, img: &DynamicImage, metadata: &Metadata) -> Result<Vec<u8>, String> {
+    // QOI only supports 8-bit RGB(A)
+    let rgba = DynamicImage::ImageRgba8(img.to_rgba8());
+    let mut buf = Vec::new();
+    write_with(&rgba, QoiEncoder::new(&mut buf), metadata)?;
+    Ok(buf)
+  }
+}
+
+fn write_with(img: &DynamicImage, mut encoder: impl ImageEncoder, metadata: &Metadata) -> Result<(), String> {
+  if let Some(icc) = &metadata.icc {
+    encoder.set_icc_profile(icc.clone()).map_err(|e| e.to_string())?;
+  }
+  if let Some(exif) = &metadata.exif {
+    encoder.set_exif_metadata(exif.clone()).map_err(|e| e.to_string())?;
+  }
+  img.write_with_encoder(encoder).map_err(|e| e.to_string())
+}
+
+/// Rewrites the simple or extended WebP written by libwebp into an extended WebP with `ICCP` and `EXIF` chunks.
+fn mux_webp(webp: &[u8], width: u32, height: u32, metadata: &Metadata) -> Result<Vec<u8>, String> {
+  const ICC: u8 = 0x20;
+  const ALPHA: u8 = 0x10;
+  const EXIF: u8 = 0x08;
+
+  if webp.len() < 12 || &webp[..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+    return Err("libwebp did not return a RIFF WebP".into());
+  }
+  let mut chunks = Vec::new();
+  let mut at = 12;
+  while at + 8 <= webp.len() {
+    let size = u32::from_le_bytes(webp[at + 4..at + 8].try_into().unwrap()) as usize;
+    let payload = webp.get(at + 8..at + 8 + size).ok_or("libwebp returned a truncated WebP")?;
+    chunks.push((&webp[at..at + 4], payload));
+    at += 8 + size + size % 2;
+  }
+
+  let mut vp8x = match chunks.first() {
+    Some((b"VP8X", payload)) => payload.to_vec(),
+    _ => {
+      let mut payload = vec![0; 10];
+      payload[4..7].copy_from_slice(&(width - 1).to_le_bytes()[..3]);
+      payload[7..10].copy_from_slice(&(height - 1).to_le_bytes()[..3]);
+      payload
+    },
+  };
+  // a lossless bitstream tells whether its alpha is used, a lossy one has a separate ALPH chunk
+  let alpha = chunks.iter().any(|(id, payload)| match *id {
+    b"ALPH" => true,
+    b"VP8L" => payload.get(4).is_some_and(|b| b & 0x10 != 0),
+    _ => false,
+  });
+  if alpha {
+    vp8x[0] |= ALPHA;
+  }
+  if metadata.icc.is_some() {
+    vp8x[0] |= ICC;
+  }
+  if metadata.exif.is_some() {
+    vp8x[0] |= EXIF;
+  }
+
+  let mut body = b"WEBP".to_vec();
+  let mut push = |id: &[u8], payload: &[u8]| {
+    body.extend_from_slice(id);
+    body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    body.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+      body.push(0);
+    }
+  };
+  push(b"VP8X", &vp8x);
+  if let Some(icc) = &metadata.icc {
+    push(b"ICCP", icc);
+  }
+  for (id, payload) in chunks.iter().filter(|(id, _)| !matches!(*id, b"VP8X" | b"ICCP" | b"EXIF")) {
+    push(id, payload);
+  }
+  if let Some(exif) = &metadata.exif {
+    push(b"EXIF", exif);
+  }
+
+  let mut buf = b"RIFF".to_vec();
+  buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+  buf.extend_from_slice(&body);
+  Ok(buf)
+}
+
+fn webp_config(opt: &WebPOption) -> WebPConfig {
+  let mut config = WebPConfig::new().unwrap();
+  config.lossless = opt.lossless as i32;
+  config.quality = opt.quality;
+  config.method = opt.method;
+  config.image_hint = match opt.image_hint {
+    WebPImageHint::Default => WEBP_HINT_DEFAULT,
+    WebPImageHint::Picture => WEBP_HINT_PICTURE,
+    WebPImageHint::Photo => WEBP_HINT_PHOTO,
+    WebPImageHint::Graph => WEBP_HINT_GRAPH,
+  };
+  config.target_size = opt.target_size;
+  config.target_PSNR = opt.target_psnr;
+  config.segments = opt.segments;
+  config.sns_strength = opt.sns_strength;
+  config.filter_strength = opt.filter_strength;
+  config.filter_sharpness = opt.filter_sharpness;
+  config.filter_type = opt.filter_type;
+  config.autofilter = opt.autofilter as i32;
+  config.alpha_compression = opt.alpha_compression;
+  config.alpha_filtering = opt.alpha_filtering;
+  config.alpha_quality = opt.alpha_quality;
+  config.pass = opt.pass;
+  config.show_compressed = opt.show_compressed as i32;
+  config.preprocessing = opt.preprocessing;
+  config.partitions = opt.partitions;
+  config.partition_limit = opt.partition_limit;
+  config.emulate_jpeg_size = opt.emulate_jpeg_size as i32;
+  config.thread_level = opt.thread_level;
+  config.low_memory = opt.low_memory as i32;
+  config.near_lossless = opt.near_lossless;
+  config.exact = opt.exact as i32;
+  config.use_delta_palette = opt.use_delta_palette as i32;
+  config.use_sharp_yuv = opt.use_sharp_yuv as i32;
+  config.qmin = opt.qmin;
+  config.qmax = opt.qmax;
+  config
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut body = b"WEBP".to_vec();
+    for (id, payload) in chunks {
+      body.extend_from_slice(*id);
+      body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+      body.extend_from_slice(payload);
+      if payload.len() % 2 == 1 {
+        body.push(0);
+      }
+    }
+    let mut buf = b"RIFF".to_vec();
+    buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&body);
+    buf
+  }
+
+  /// Splits a WebP into its chunks, checking the RIFF size and the padding of every chunk.
+  fn split_chunks(webp: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+    assert_eq!(&webp[..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(webp[4..8].try_into().unwrap()) as usize, webp.len() - 8);
+    assert_eq!(&webp[8..12], b"WEBP");
+    let mut chunks = Vec::new();
+    let mut at = 12;
+    while at < webp.len() {
+      let size = u32::from_le_bytes(webp[at + 4..at + 8].try_into().unwrap()) as usize;
+      chunks.push((webp[at..at + 4].try_into().unwrap(), webp[at + 8..at + 8 + size].to_vec()));
+      if size % 2 == 1 {
+        assert_eq!(webp[at + 8 + size], 0);
+      }
+      at += 8 + size + size % 2;
+    }
+    assert_eq!(at, webp.len());
+    chunks
+  }
+
+  fn ids(chunks: &[([u8; 4], Vec<u8>)]) -> Vec<&[u8]> {
+    chunks.iter().map(|(id, _)| id.as_slice()).collect()
+  }
+
+  fn canvas(vp8x: &[u8]) -> (u32, u32) {
+    let u24 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], 0]) + 1;
+    (u24(&vp8x[4..7]), u24(&vp8x[7..10]))
+  }
+
+  const VP8: &[u8] = b"lossy";
+
+  #[test]
+  fn mux_webp_adds_icc_and_exif() {
+    let metadata = Metadata { icc: Some(b"icc".to_vec()), exif: Some(b"II*\0exif".to_vec()) };
+    let webp = mux_webp(&riff(&[(b"VP8 ", VP8)]), 1920, 1080, &metadata).unwrap();
+
+    let chunks = split_chunks(&webp);
+    assert_eq!(ids(&chunks), [b"VP8X", b"ICCP", b"VP8 ", b"EXIF"]);
+    let vp8x = &chunks[0].1;
+    assert_eq!(vp8x.len(), 10);
+    assert_eq!(vp8x[0], 0x20 | 0x08);
+    assert_eq!(canvas(vp8x), (1920, 1080));
+    assert_eq!(chunks[1].1, b"icc");
+    assert_eq!(chunks[2].1, VP8);
+    assert_eq!(chunks[3].1, b"II*\0exif");
+  }
+
+  #[test]
+  fn mux_webp_adds_icc_only() {
+    let metadata = Metadata { icc: Some(b"icc".to_vec()), exif: None };
+    let webp = mux_webp(&riff(&[(b"VP8 ", VP8)]), 640, 360, &metadata).unwrap();
+
+    let chunks = split_chunks(&webp);
+    assert_eq!(ids(&chunks), [b"VP8X", b"ICCP", b"VP8 "]);
+    assert_eq!(chunks[0].1[0], 0x20);
+    assert_eq!(canvas(&chunks[0].1), (640, 360));
+  }
+
+  #[test]
+  fn mux_webp_adds_exif_only() {
+    let metadata = Metadata { icc: None, exif: Some(b"II*\0".to_vec()) };
+    let webp = mux_webp(&riff(&[(b"VP8 ", VP8)]), 640, 360, &metadata).unwrap();
+
+    let chunks = split_chunks(&webp);
+    assert_eq!(ids(&chunks), [b"VP8X", b"VP8 ", b"EXIF"]);
+    assert_eq!(chunks[0].1[0], 0x08);
+  }
+
+  #[test]
+  fn mux_webp_flags_alpha() {
+    let metadata = Metadata { icc: None, exif: Some(b"II*\0".to_vec()) };
+
+    // a lossy image with an ALPH chunk, already in an extended WebP
+    let mut vp8x = vec![0x10, 0, 0, 0];
+    vp8x.extend_from_slice(&[99, 0, 0, 49, 0, 0]);
+    let webp = riff(&[(b"VP8X", &vp8x), (b"ALPH", b"a"), (b"VP8 ", VP8)]);
+    let chunks = split_chunks(&mux_webp(&webp, 100, 50, &metadata).unwrap());
+    assert_eq!(ids(&chunks), [b"VP8X", b"ALPH", b"VP8 ", b"EXIF"]);
+    assert_eq!(chunks[0].1[0], 0x10 | 0x08);
+    assert_eq!(canvas(&chunks[0].1), (100, 50));
+
+    // the alpha_is_used bit of a lossless bitstream
+    for (header, flags) in [(0x10, 0x10 | 0x08), (0x00, 0x08)] {
+      let vp8l = [0x2f, 0, 0, 0, header];
+      let chunks = split_chunks(&mux_webp(&riff(&[(b"VP8L", &vp8l)]), 1, 1, &metadata).unwrap());
+      assert_eq!(chunks[0].1[0], flags);
+    }
+  }
+
+  #[test]
+  fn mux_webp_rejects_other_files() {
+    assert!(mux_webp(b"RIFF\0\0\0\0WAVE", 1, 1, &Metadata::default()).is_err());
+    let mut truncated = riff(&[(b"VP8 ", VP8)]);
+    truncated.truncate(truncated.len() - 2);
+    assert!(mux_webp(&truncated, 1, 1, &Metadata::default()).is_err());
+  }
+}
@@ -1,62 +1,335 @@
 #[cfg(feature = "cli")]
-use clap::{Subcommand, ValueEnum};
+use clap::ValueEnum;
+use image::imageops::FilterType;
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
-#[cfg_attr(feature = "cli", derive(ValueEnum))]
-#[serde(rename_all = "lowercase")]
+use crate::structs::Coord;
+
+/// Part of the image kept by cropping. Every position but `Full` and `Rect` keeps `crop_height` rows of the full width.
+/// Written as a name like `"bottom"`, as `{ y = 120 }`, or as an `[x, y, width, height]` array.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "CropPositionRepr", into = "CropPositionRepr")]
 pub enum CropPosition {
+  Top,
   Bottom,
   Center,
+  /// The whole image
   Full,
+  /// The image without the dark bars around it, such as the letterbox of a cutscene
+  Auto,
+  /// Rows from `y`, in `reference` pixels
+  Custom {
+    y: u32,
+  },
+  /// `[x, y, width, height]` from the top left corner, in `reference` pixels or percent like a blur area
+  Rect([Coord; 4]),
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
-#[cfg_attr(feature = "cli", derive(Subcommand, ValueEnum))]
-pub enum Operation {
-  /// Process everything [alias: a]
-  #[cfg_attr(feature = "cli", clap(aliases = ["a"]))]
-  All,
-  /// Background [alias: b, bg]
-  #[cfg_attr(feature = "cli", clap(aliases = ["b", "bg"]))]
-  Background,
-  /// Center [alias: c]
-  #[cfg_attr(feature = "cli", clap(aliases = ["c"]))]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CropPositionRepr {
+  Name(String),
+  Custom { y: u32 },
+  Rect([Coord; 4]),
+}
+
+impl TryFrom<CropPositionRepr> for CropPosition {
+  type Error = String;
+
+  fn try_from(repr: CropPositionRepr) -> Result<Self, Self::Error> {
+    match repr {
+      CropPositionRepr::Name(name) => match name.as_str() {
+        "top" => Ok(CropPosition::Top),
+        "bottom" => Ok(CropPosition::Bottom),
+        "center" => Ok(CropPosition::Center),
+        "full" => Ok(CropPosition::Full),
+        "auto" => Ok(CropPosition::Auto),
+        _ => Err(format!("Unknown crop position '{}', expected top, bottom, center, full or auto", name)),
+      },
+      CropPositionRepr::Custom { y } => Ok(CropPosition::Custom { y }),
+      CropPositionRepr::Rect(area) => Ok(CropPosition::Rect(area)),
+    }
+  }
+}
+
+impl From<CropPosition> for CropPositionRepr {
+  fn from(pos: CropPosition) -> Self {
+    match pos {
+      CropPosition::Top => CropPositionRepr::Name("top".into()),
+      CropPosition::Bottom => CropPositionRepr::Name("bottom".into()),
+      CropPosition::Center => CropPositionRepr::Name("center".into()),
+      CropPosition::Full => CropPositionRepr::Name("full".into()),
+      CropPosition::Auto => CropPositionRepr::Name("auto".into()),
+      CropPosition::Custom { y } => CropPositionRepr::Custom { y },
+      CropPosition::Rect(area) => CropPositionRepr::Rect(area),
+    }
+  }
+}
+
+/// Corner or edge of the image that the `x` and `y` of a blur region are measured from.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+  #[default]
+  TopLeft,
+  Top,
+  TopRight,
+  Left,
   Center,
-  /// Create Directories [alias: cd]
-  #[cfg_attr(feature = "cli", clap(aliases = ["cd"]))]
-  CreateDirectory,
-  /// Cutscene [alias: cs, s]
-  #[cfg_attr(feature = "cli", clap(aliases = ["cs", "s"]))]
-  Cutscene,
-  /// Foreground with 0 option [alias: f0, fg0]
-  #[cfg_attr(feature = "cli", clap(aliases = ["f0", "fg0"]))]
-  Foreground0,
-  /// Foreground with 1 option [alias: f1, fg1]
-  #[cfg_attr(feature = "cli", clap(aliases = ["f1", "fg1"]))]
-  Foreground1,
-  /// Foreground with 2 options [alias: f2, fg2]
-  #[cfg_attr(feature = "cli", clap(aliases = ["f2", "fg2"]))]
-  Foreground2,
-  /// Foreground with 3 options [alias: f3, fg3]
-  #[cfg_attr(feature = "cli", clap(aliases = ["f3", "fg3"]))]
-  Foreground3,
-  /// Foreground with 4 options [alias: f4, fg4]
-  #[cfg_attr(feature = "cli", clap(aliases = ["f4", "fg4"]))]
-  Foreground4,
-  /// Foreground with 5 options [alias: f5, fg5]
-  #[cfg_attr(feature = "cli", clap(aliases = ["f5", "fg5"]))]
-  Foreground5,
-  /// Fullscreen [alias: f]
-  #[cfg_attr(feature = "cli", clap(aliases = ["f"]))]
-  Full,
+  Right,
+  BottomLeft,
+  Bottom,
+  BottomRight,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum RedactStyle {
+  /// Box blur, strength is the kernel size
+  #[default]
+  Box,
+  /// Gaussian blur, strength is the kernel size
+  Gaussian,
+  /// Mosaic, strength is the block size
+  #[cfg_attr(feature = "cli", value(alias = "mosaic"))]
+  #[serde(alias = "mosaic")]
+  Pixelate,
+  /// Fill with `color`, strength is unused
+  Solid,
+  /// Random noise, strength is the grain size
+  Noise,
+}
+
+impl RedactStyle {
+  /// Strength used when a region does not set one.
+  pub fn default_strength(self) -> u32 {
+    match self {
+      RedactStyle::Box | RedactStyle::Gaussian => 45,
+      RedactStyle::Pixelate => 16,
+      RedactStyle::Solid | RedactStyle::Noise => 1,
+    }
+  }
+}
+
+/// How the cropped image is resized. Every mode but `Scale` only ever shrinks it.
+/// Written as a table with one key, such as `{ fit_width = 1280 }` or `{ fit_box = [1280, 720] }`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeMode {
+  /// Width in pixels
+  FitWidth(u32),
+  /// Height in pixels
+  FitHeight(u32),
+  /// Largest size within `[width, height]` in pixels
+  FitBox([u32; 2]),
+  /// Factor applied to both sides
+  Scale(f64),
+  /// Largest size of at most this many million pixels
+  MaxMegapixels(f64),
+}
+
+impl ResizeMode {
+  /// Largest factor of `Scale`, which already turns a 4K screenshot into 132 million pixels
+  pub const MAX_SCALE: f64 = 4.0;
+
+  /// Returns the size a `width`x`height` image is resized to, or `None` if it is kept as is.
+  pub fn size(self, width: u32, height: u32) -> Result<Option<[u32; 2]>, String> {
+    let (w, h) = (width as f64, height as f64);
+    let factor = match self {
+      ResizeMode::FitWidth(0) | ResizeMode::FitHeight(0) | ResizeMode::FitBox([0, _] | [_, 0]) => {
+        return Err(format!("Resize target must not be 0: {:?}", self));
+      },
+      ResizeMode::Scale(f) | ResizeMode::MaxMegapixels(f) if !(f > 0.0 && f.is_finite()) => {
+        return Err(format!("Resize factor must be positive: {:?}", self));
+      },
+      ResizeMode::Scale(f) if f > Self::MAX_SCALE => {
+        return Err(format!("Resize factor must not exceed {}: {:?}", Self::MAX_SCALE, self));
+      },
+      ResizeMode::FitWidth(tw) => (tw as f64 / w).min(1.0),
+      ResizeMode::FitHeight(th) => (th as f64 / h).min(1.0),
+      ResizeMode::FitBox([bw, bh]) => (bw as f64 / w).min(bh as f64 / h).min(1.0),
+      ResizeMode::Scale(f) => f,
+      ResizeMode::MaxMegapixels(mp) => (mp * 1_000_000.0 / (w * h)).sqrt().min(1.0),
+    };
+    // rounding up could go past the pixel limit
+    let round = if matches!(self, ResizeMode::MaxMegapixels(_)) { f64::floor } else { f64::round };
+    let size = [(round(w * factor) as u32).max(1), (round(h * factor) as u32).max(1)];
+    Ok((size != [width, height]).then_some(size))
+  }
+}
+
+/// Resampling filter used to resize, from the fastest and blockiest to the slowest and sharpest.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeFilter {
+  Nearest,
+  Triangle,
+  CatmullRom,
+  Gaussian,
+  #[default]
+  Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+  fn from(filter: ResizeFilter) -> Self {
+    match filter {
+      ResizeFilter::Nearest => FilterType::Nearest,
+      ResizeFilter::Triangle => FilterType::Triangle,
+      ResizeFilter::CatmullRom => FilterType::CatmullRom,
+      ResizeFilter::Gaussian => FilterType::Gaussian,
+      ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebPImageHint {
+  Default,
+  Picture,
+  Photo,
+  Graph,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum WebPMode {
+  Lossy,
+  Lossless,
+  NearLossless,
+}
+
+/// What to do when an output file already exists, or two inputs of a batch would be written to the same file.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+  /// Keep the existing file and leave the input unconverted
+  Skip,
+  /// Replace the existing file
+  #[default]
+  Overwrite,
+  /// Write to the first free '<stem>-<n>.<ext>' instead
+  Rename,
+  /// Fail the input
+  Error,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 #[cfg_attr(feature = "cli", derive(ValueEnum))]
-pub enum Game {
-  #[cfg_attr(feature = "cli", clap(aliases = ["n"]))]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+  #[cfg_attr(feature = "cli", clap(name = "webp"))]
+  WebP,
+  Png,
+  #[cfg_attr(feature = "cli", clap(alias = "jpg"))]
+  Jpeg,
+  Avif,
+  Qoi,
+}
+
+/// What happens to an image once its output is written and verified
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum OriginalsAction {
+  /// Leave the image where it is
+  #[default]
+  Keep,
+  /// Move the image into '<out_dir>/originals/<target folder>/', keeping subdirectories
+  Move,
+  /// Pack the images of the run into '<out_dir>/originals/<target folder>-<time>.zip'
+  Zip,
+  /// Send the image to the trash
+  Trash,
+}
+
+/// EXIF field carried from the input into the output
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExifField {
+  /// 'DateTimeOriginal' and 'OffsetTimeOriginal', or the modification time of the input if it has none
+  CaptureTime,
+  /// 'Software' of the input
+  Software,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+  Default,
+  Fast,
+  Best,
+  Uncompressed,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngFilter {
+  Adaptive,
   None,
-  #[cfg_attr(feature = "cli", clap(name = "wuwa", alias = "w"))]
-  WuWa,
+  Sub,
+  Up,
+  Avg,
+  Paeth,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn resize_rejects_zero_targets() {
+    for mode in
+      [ResizeMode::FitWidth(0), ResizeMode::FitHeight(0), ResizeMode::FitBox([0, 720]), ResizeMode::FitBox([1280, 0])]
+    {
+      assert!(mode.size(1920, 1080).unwrap_err().contains("must not be 0"), "{:?}", mode);
+    }
+  }
+
+  #[test]
+  fn resize_rejects_non_positive_factors() {
+    for f in [0.0, -0.5, f64::NAN, f64::INFINITY] {
+      for mode in [ResizeMode::Scale(f), ResizeMode::MaxMegapixels(f)] {
+        assert!(mode.size(1920, 1080).unwrap_err().contains("must be positive"), "{:?}", mode);
+      }
+    }
+  }
+
+  #[test]
+  fn resize_rejects_scale_above_max() {
+    assert_eq!(ResizeMode::Scale(ResizeMode::MAX_SCALE).size(10, 10), Ok(Some([40, 40])));
+    assert!(ResizeMode::Scale(4.01).size(10, 10).unwrap_err().contains("must not exceed 4"));
+    assert!(ResizeMode::Scale(1e12).size(1920, 1080).is_err());
+  }
+
+  #[test]
+  fn resize_fits_without_upscaling() {
+    assert_eq!(ResizeMode::FitWidth(1280).size(1920, 1080), Ok(Some([1280, 720])));
+    assert_eq!(ResizeMode::FitHeight(540).size(1920, 1080), Ok(Some([960, 540])));
+    assert_eq!(ResizeMode::FitBox([960, 960]).size(1920, 1080), Ok(Some([960, 540])));
+    assert_eq!(ResizeMode::FitWidth(3840).size(1920, 1080), Ok(None));
+    assert_eq!(ResizeMode::FitBox([1920, 1080]).size(1920, 1080), Ok(None));
+  }
+
+  #[test]
+  fn resize_scales_both_ways() {
+    assert_eq!(ResizeMode::Scale(2.0).size(1920, 1080), Ok(Some([3840, 2160])));
+    assert_eq!(ResizeMode::Scale(0.5).size(1920, 1080), Ok(Some([960, 540])));
+    assert_eq!(ResizeMode::Scale(1.0).size(1920, 1080), Ok(None));
+    assert_eq!(ResizeMode::Scale(0.001).size(100, 100), Ok(Some([1, 1])));
+  }
+
+  #[test]
+  fn resize_floors_max_megapixels() {
+    // 1732.05 x 1154.70, rounding would give 1732 x 1155 = 2000460 pixels
+    let [w, h] = ResizeMode::MaxMegapixels(2.0).size(3000, 2000).unwrap().unwrap();
+    assert_eq!([w, h], [1732, 1154]);
+    assert!(w * h <= 2_000_000);
+    assert_eq!(ResizeMode::MaxMegapixels(2.1).size(1920, 1080), Ok(None));
+  }
 }
@@ -1,9 +1,18 @@
+pub mod encoder;
 pub mod enums;
+pub mod manifest;
+pub mod metadata;
+pub mod originals;
+pub mod pipeline;
+pub mod report;
 pub mod structs;
+pub mod template;
 
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::structs::{MergedOption, ScanOption};
+
 pub fn adjust_extension(file_name: &str) -> String {
   let path = Path::new(file_name);
   if path.extension().is_some() {
@@ -15,38 +24,90 @@ pub fn adjust_extension(file_name: &str) -> String {
   }
 }
 
-pub fn dir_has_image(dir: &Path) -> bool {
-  match fs::read_dir(dir) {
-    Ok(e) => e.flatten().any(|entry| {
-      let path = entry.path();
-      entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) && is_image_file(&path)
-    }),
-    Err(_) => false,
-  }
+/// Name of the directory converted images are saved to when `save_at_parent` is not set.
+/// Directories with this name are never searched for images.
+pub const OUTPUT_DIR_NAME: &str = "converted";
+
+/// Name of the directory in the output directory that originals are moved or archived into after conversion.
+/// Directories with this name are never searched for images.
+pub const ORIGINALS_DIR_NAME: &str = "originals";
+
+/// Returns `true` if the given directory contains any image file (jpg, jpeg, png, webp) that passes `scan`.
+pub fn dir_has_image(dir: &Path, scan: &ScanOption) -> bool {
+  let mut found = false;
+  walk_image(dir, scan, &mut |_| {
+    found = true;
+    false
+  });
+  found
 }
 
-/// Returns `true` if the given directory contains any image file (jpg, jpeg, png, webp).
-pub fn dir_collect_image(dir: &Path) -> Vec<PathBuf> {
+/// Returns `Vec<PathBuf>` of absolute paths of image files in the given directory that pass `scan`, sorted by path.
+/// Subdirectories are only searched if `scan.recursive` is set.
+pub fn dir_collect_image(dir: &Path, scan: &ScanOption) -> Vec<PathBuf> {
   let mut images = Vec::new();
+  walk_image(dir, scan, &mut |path| {
+    if let Ok(c) = path.canonicalize() {
+      images.push(c);
+    }
+    true
+  });
+  images.sort();
+  images
+}
 
-  if let Ok(entries) = fs::read_dir(dir) {
+/// Returns `<out_dir>/<prefix>.<game>.<layer>.<ext>`, the file of `mo` that a run keeps next to its outputs, such as
+/// the manifest or the report. Such files are named after the game and layer, as layers converted with 'all' share
+/// the output directory.
+pub fn layer_file(mo: &MergedOption, out_dir: &Path, prefix: &str, ext: &str) -> PathBuf {
+  out_dir.join(format!("{}.{}.{}.{}", prefix, mo.game, mo.layer, ext))
+}
+
+/// Returns the directory converted images of `mo` are saved to.
+pub fn output_dir(mo: &MergedOption) -> PathBuf {
+  let target = &mo.target;
+  if mo.save_at_parent {
+    target.parent().and_then(|p| if p.parent().is_some() { Some(p.to_path_buf()) } else { None }).unwrap_or_else(|| {
+      let mut s = target.clone();
+      s.set_file_name(format!("{}-converted", target.file_name().unwrap().to_string_lossy()));
+      s
+    })
+  } else {
+    target.join(OUTPUT_DIR_NAME)
+  }
+}
+
+/// Calls `f` with every image file under `root` that passes `scan`, until `f` returns `false`.
+fn walk_image(root: &Path, scan: &ScanOption, f: &mut impl FnMut(PathBuf) -> bool) {
+  let include = scan.include_set();
+  let exclude = scan.exclude_set();
+  let mut dirs = vec![root.to_path_buf()];
+
+  while let Some(dir) = dirs.pop() {
+    let Ok(entries) = fs::read_dir(&dir) else {
+      continue;
+    };
     for entry in entries.flatten() {
       let path = entry.path();
-      if !entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
+      let Ok(ft) = entry.file_type() else {
+        continue;
+      };
+      // match globs against the path relative to 'root', with '/' as separator on every platform
+      let rel = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+      if exclude.is_match(&rel) {
         continue;
       }
-      if is_image_file(&path) {
-        if let Ok(c) = path.canonicalize() {
-          images.push(c);
+      if ft.is_dir() {
+        if scan.recursive && entry.file_name() != OUTPUT_DIR_NAME && entry.file_name() != ORIGINALS_DIR_NAME {
+          dirs.push(path);
         }
+      } else if ft.is_file() && is_image_file(&path) && (include.is_empty() || include.is_match(&rel)) && !f(path) {
+        return;
       }
     }
   }
-  images
 }
 
-/// Returns `Vec<PathBuf>` of absolute paths of image files in the given directory.
-/// This does not search subdirectories.
 fn is_image_file(p: &Path) -> bool {
   p.extension()
     .and_then(|s| s.to_str())
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::enums::{ConflictPolicy, OriginalsAction};
+use crate::layer_file;
+use crate::structs::{MergedOption, OutputOption, ScanOption};
+
+/// Record of the images converted into an output directory, used by incremental runs.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+  /// Effective options of the run that wrote the manifest
+  options: Option<MergedOption>,
+  /// Keyed by the input path relative to the target directory
+  entries: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+  size: u64,
+  /// Nanoseconds since the Unix epoch
+  mtime: u64,
+  /// BLAKE3 hash of the input, in hex
+  hash: String,
+  output: PathBuf,
+}
+
+impl Manifest {
+  /// Returns the path of the manifest of `mo` in `out_dir`, see [`layer_file`].
+  pub fn path(mo: &MergedOption, out_dir: &Path) -> PathBuf {
+    layer_file(mo, out_dir, ".cs-manifest", "json")
+  }
+
+  /// Loads the manifest of `mo` from `out_dir`. It starts over if missing, unreadable, or written with other settings.
+  pub fn load(mo: &MergedOption, out_dir: &Path) -> Manifest {
+    let manifest: Manifest =
+      fs::read(Self::path(mo, out_dir)).ok().and_then(|buf| serde_json::from_slice(&buf).ok()).unwrap_or_default();
+    match &manifest.options {
+      Some(options) if settings(options) == settings(mo) => manifest,
+      _ => Manifest::default(),
+    }
+  }
+
+  pub fn save(&mut self, mo: &MergedOption, out_dir: &Path) -> Result<(), String> {
+    self.options = Some(mo.clone());
+    let path = Self::path(mo, out_dir);
+    let buf = serde_json::to_vec_pretty(self).map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    fs::create_dir_all(out_dir)
+      .and_then(|_| fs::write(&path, buf))
+      .map_err(|e| format!("Failed to write manifest '{}': {}", path.display(), e))
+  }
+
+  /// Returns the up-to-date entry of `f` if it is unchanged since it was converted into `output`, and `output` still
+  /// exists. Only a touched input is hashed again.
+  pub fn check(&self, f: &Path, mo: &MergedOption, output: &Path) -> Option<ManifestEntry> {
+    let entry = self.entries.get(&key(f, mo))?;
+    if entry.output != output || !output.exists() {
+      return None;
+    }
+    let (size, mtime) = stat(f).ok()?;
+    if size != entry.size {
+      return None;
+    }
+    if mtime == entry.mtime {
+      return Some(entry.clone());
+    }
+    let hash = hash(f).ok()?;
+    (hash == entry.hash).then(|| ManifestEntry { mtime, ..entry.clone() })
+  }
+
+  /// Whether `output` was written for `f` by an earlier incremental run.
+  pub fn owns(&self, f: &Path, mo: &MergedOption, output: &Path) -> bool {
+    self.entries.get(&key(f, mo)).is_some_and(|entry| entry.output == output)
+  }
+
+  pub fn insert(&mut self, f: &Path, mo: &MergedOption, entry: ManifestEntry) {
+    self.entries.insert(key(f, mo), entry);
+  }
+
+  pub fn remove(&mut self, f: &Path, mo: &MergedOption) {
+    self.entries.remove(&key(f, mo));
+  }
+}
+
+impl ManifestEntry {
+  /// Records `f` as converted into `output`.
+  pub fn read(f: &Path, output: &Path) -> io::Result<ManifestEntry> {
+    let (size, mtime) = stat(f)?;
+    Ok(ManifestEntry { size, mtime, hash: hash(f)?, output: output.to_path_buf() })
+  }
+}
+
+/// Options that change the output of an image. The number of jobs, which images are scanned, how conflicts are
+/// resolved, whether unblurred images are allowed and what happens to originals do not.
+fn settings(mo: &MergedOption) -> serde_json::Value {
+  let scan = ScanOption { mirror: mo.scan.mirror, ..Default::default() };
+  let output = OutputOption { on_conflict: ConflictPolicy::default(), ..mo.output.clone() };
+  serde_json::to_value(MergedOption {
+    allow_unblurred: false,
+    jobs: 0,
+    incremental: false,
+    originals: OriginalsAction::default(),
+    output,
+    scan,
+    ..mo.clone()
+  })
+  .unwrap_or_default()
+}
+
+fn key(f: &Path, mo: &MergedOption) -> String {
+  let root = mo.target.canonicalize().unwrap_or_else(|_| mo.target.clone());
+  let rel = f.strip_prefix(&root).unwrap_or(f);
+  rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+fn stat(f: &Path) -> io::Result<(u64, u64)> {
+  let meta = fs::metadata(f)?;
+  let mtime = meta.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+  Ok((meta.len(), mtime))
+}
+
+fn hash(f: &Path) -> io::Result<String> {
+  let mut hasher = blake3::Hasher::new();
+  hasher.update_reader(fs::File::open(f)?)?;
+  Ok(hasher.finalize().to_hex().to_string())
+}
@@ -0,0 +1,268 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+use image::{DynamicImage, ImageDecoder, ImageReader};
+
+use crate::enums::ExifField;
+use crate::structs::MetadataOption;
+
+const TAG_SOFTWARE: u16 = 0x0131;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+
+const TYPE_ASCII: u16 = 2;
+const TYPE_LONG: u16 = 4;
+/// Offset to a sub-IFD, written as LONG by most cameras
+const TYPE_IFD: u16 = 13;
+
+/// ICC profile and EXIF data embedded into an output file.
+#[derive(Default)]
+pub struct Metadata {
+  pub icc: Option<Vec<u8>>,
+  /// TIFF structure, without the 'Exif\0\0' header of JPEG
+  pub exif: Option<Vec<u8>>,
+}
+
+/// Decodes `f` along with the metadata that `opt` carries into the output. `None` strips everything.
+pub fn open_image(f: &Path, opt: Option<&MetadataOption>) -> Result<(DynamicImage, Metadata), String> {
+  let open_err = |e: image::ImageError| format!("Failed to open '{}': {}", f.display(), e);
+  let mut decoder = ImageReader::open(f)
+    .map_err(|e| format!("Failed to open '{}': {}", f.display(), e))?
+    .with_guessed_format()
+    .map_err(|e| format!("Failed to open '{}': {}", f.display(), e))?
+    .into_decoder()
+    .map_err(open_err)?;
+
+  let mut metadata = Metadata::default();
+  if let Some(opt) = opt {
+    // metadata that cannot be read is left out rather than failing the image
+    if opt.icc {
+      metadata.icc = decoder.icc_profile().ok().flatten().filter(|icc| !icc.is_empty());
+    }
+    if !opt.exif.is_empty() {
+      let exif = decoder.exif_metadata().ok().flatten().unwrap_or_default();
+      metadata.exif = select_exif(f, Tiff::new(&exif), &opt.exif);
+    }
+  }
+
+  let img = DynamicImage::from_decoder(decoder).map_err(open_err)?;
+  Ok((img, metadata))
+}
+
+/// Builds the EXIF of the output from the `fields` of the input EXIF.
+fn select_exif(f: &Path, tiff: Option<Tiff>, fields: &[ExifField]) -> Option<Vec<u8>> {
+  let mut ifd0 = Vec::new();
+  let mut exif_ifd = Vec::new();
+  if fields.contains(&ExifField::Software)
+    && let Some(software) = tiff.as_ref().and_then(|t| t.ascii(t.ifd0()?, TAG_SOFTWARE))
+  {
+    ifd0.push((TAG_SOFTWARE, software));
+  }
+  if fields.contains(&ExifField::CaptureTime) {
+    let exif = tiff.as_ref().and_then(|t| t.long(t.ifd0()?, TAG_EXIF_IFD));
+    let original = exif.and_then(|ifd| {
+      let t = tiff.as_ref()?;
+      Some((t.ascii(ifd, TAG_DATE_TIME_ORIGINAL)?, t.ascii(ifd, TAG_OFFSET_TIME_ORIGINAL)))
+    });
+    let (time, offset) = match original {
+      Some(original) => original,
+      None => {
+        let mtime = DateTime::<Local>::from(fs::metadata(f).and_then(|meta| meta.modified()).ok()?);
+        (mtime.format("%Y:%m:%d %H:%M:%S").to_string(), Some(mtime.format("%:z").to_string()))
+      },
+    };
+    exif_ifd.push((TAG_DATE_TIME_ORIGINAL, time));
+    if let Some(offset) = offset {
+      exif_ifd.push((TAG_OFFSET_TIME_ORIGINAL, offset));
+    }
+  }
+  (!ifd0.is_empty() || !exif_ifd.is_empty()).then(|| write_exif(&ifd0, &exif_ifd))
+}
+
+/// Writes a little-endian TIFF structure holding the ASCII `ifd0` entries, and the ASCII `exif_ifd` entries in an Exif
+/// IFD if any. Entries must be sorted by tag.
+fn write_exif(ifd0: &[(u16, String)], exif_ifd: &[(u16, String)]) -> Vec<u8> {
+  let mut buf = b"II*\0".to_vec();
+  buf.extend_from_slice(&8u32.to_le_bytes());
+  let mut entries: Vec<(u16, Value)> = ifd0.iter().map(|(tag, s)| (*tag, Value::Ascii(s))).collect();
+  if !exif_ifd.is_empty() {
+    // the offset is patched once the size of IFD0 is known
+    entries.push((TAG_EXIF_IFD, Value::Long(0)));
+  }
+  let pointer = write_ifd(&mut buf, &entries);
+  if !exif_ifd.is_empty() {
+    let offset = buf.len() as u32;
+    buf[pointer..pointer + 4].copy_from_slice(&offset.to_le_bytes());
+    let entries: Vec<(u16, Value)> = exif_ifd.iter().map(|(tag, s)| (*tag, Value::Ascii(s))).collect();
+    write_ifd(&mut buf, &entries);
+  }
+  buf
+}
+
+enum Value<'a> {
+  Ascii(&'a str),
+  Long(u32),
+}
+
+/// Appends an IFD followed by the values that do not fit in its entries. Returns the position of the value of the
+/// last LONG entry.
+fn write_ifd(buf: &mut Vec<u8>, entries: &[(u16, Value)]) -> usize {
+  let start = buf.len();
+  let mut data_offset = start + 2 + entries.len() * 12 + 4;
+  let mut data = Vec::new();
+  let mut long_pos = 0;
+  buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+  for (tag, value) in entries {
+    buf.extend_from_slice(&tag.to_le_bytes());
+    match value {
+      Value::Ascii(s) => {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.push(0);
+        buf.extend_from_slice(&TYPE_ASCII.to_le_bytes());
+        buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        if bytes.len() <= 4 {
+          bytes.resize(4, 0);
+          buf.extend_from_slice(&bytes);
+        } else {
+          buf.extend_from_slice(&(data_offset as u32).to_le_bytes());
+          // values start on a word boundary
+          if bytes.len() % 2 == 1 {
+            bytes.push(0);
+          }
+          data_offset += bytes.len();
+          data.extend_from_slice(&bytes);
+        }
+      },
+      Value::Long(v) => {
+        buf.extend_from_slice(&TYPE_LONG.to_le_bytes());
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        long_pos = buf.len();
+        buf.extend_from_slice(&v.to_le_bytes());
+      },
+    }
+  }
+  // no next IFD
+  buf.extend_from_slice(&0u32.to_le_bytes());
+  buf.extend_from_slice(&data);
+  long_pos
+}
+
+/// Reader of the few tags taken from the EXIF of the input.
+struct Tiff<'a> {
+  data: &'a [u8],
+  little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+  fn new(data: &'a [u8]) -> Option<Tiff<'a>> {
+    let data = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+    let little_endian = match data.get(..4)? {
+      b"II*\0" => true,
+      b"MM\0*" => false,
+      _ => return None,
+    };
+    Some(Tiff { data, little_endian })
+  }
+
+  fn u16(&self, at: usize) -> Option<u16> {
+    let bytes = self.data.get(at..at + 2)?.try_into().ok()?;
+    Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+  }
+
+  fn u32(&self, at: usize) -> Option<u32> {
+    let bytes = self.data.get(at..at + 4)?.try_into().ok()?;
+    Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+  }
+
+  fn ifd0(&self) -> Option<u32> {
+    self.u32(4)
+  }
+
+  /// Returns the type, count and position of the value of `tag` in the IFD at `ifd`.
+  fn entry(&self, ifd: u32, tag: u16) -> Option<(u16, u32, usize)> {
+    let ifd = ifd as usize;
+    (0..self.u16(ifd)? as usize)
+      .map(|i| ifd + 2 + i * 12)
+      .find(|&at| self.u16(at) == Some(tag))
+      .and_then(|at| Some((self.u16(at + 2)?, self.u32(at + 4)?, at + 8)))
+  }
+
+  fn ascii(&self, ifd: u32, tag: u16) -> Option<String> {
+    let (kind, count, pos) = self.entry(ifd, tag)?;
+    if kind != TYPE_ASCII {
+      return None;
+    }
+    let at = if count <= 4 { pos } else { self.u32(pos)? as usize };
+    let bytes = self.data.get(at..at + count as usize)?;
+    let s = String::from_utf8_lossy(bytes).trim_end_matches('\0').trim().to_string();
+    (!s.is_empty()).then_some(s)
+  }
+
+  fn long(&self, ifd: u32, tag: u16) -> Option<u32> {
+    match self.entry(ifd, tag)? {
+      (TYPE_LONG | TYPE_IFD, 1, pos) => self.u32(pos),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn write_exif_round_trips_through_tiff() {
+    let ifd0 = [(TAG_SOFTWARE, "convert-screenshot".to_string())];
+    let exif_ifd =
+      [(TAG_DATE_TIME_ORIGINAL, "2024:05:06 07:08:09".to_string()), (TAG_OFFSET_TIME_ORIGINAL, "+09:00".to_string())];
+    let buf = write_exif(&ifd0, &exif_ifd);
+
+    let tiff = Tiff::new(&buf).unwrap();
+    let ifd0 = tiff.ifd0().unwrap();
+    assert_eq!(tiff.ascii(ifd0, TAG_SOFTWARE).as_deref(), Some("convert-screenshot"));
+    let exif = tiff.long(ifd0, TAG_EXIF_IFD).unwrap();
+    assert_eq!(exif % 2, 0);
+    assert_eq!(tiff.ascii(exif, TAG_DATE_TIME_ORIGINAL).as_deref(), Some("2024:05:06 07:08:09"));
+    assert_eq!(tiff.ascii(exif, TAG_OFFSET_TIME_ORIGINAL).as_deref(), Some("+09:00"));
+    assert_eq!(tiff.ascii(exif, TAG_SOFTWARE), None);
+  }
+
+  #[test]
+  fn write_exif_keeps_short_values_in_the_entry() {
+    let buf = write_exif(&[(TAG_SOFTWARE, "cs".to_string())], &[]);
+    // header, one entry and the next IFD offset, without a value area
+    assert_eq!(buf.len(), 8 + 2 + 12 + 4);
+
+    let tiff = Tiff::new(&buf).unwrap();
+    let ifd0 = tiff.ifd0().unwrap();
+    assert_eq!(tiff.ascii(ifd0, TAG_SOFTWARE).as_deref(), Some("cs"));
+    assert_eq!(tiff.long(ifd0, TAG_EXIF_IFD), None);
+  }
+
+  #[test]
+  fn tiff_reads_jpeg_app1_header() {
+    let mut buf = b"Exif\0\0".to_vec();
+    buf.extend(write_exif(&[(TAG_SOFTWARE, "convert-screenshot".to_string())], &[]));
+
+    let tiff = Tiff::new(&buf).unwrap();
+    assert_eq!(tiff.ascii(tiff.ifd0().unwrap(), TAG_SOFTWARE).as_deref(), Some("convert-screenshot"));
+  }
+
+  #[test]
+  fn tiff_reads_big_endian() {
+    let mut buf = b"MM\0*".to_vec();
+    buf.extend_from_slice(&8u32.to_be_bytes());
+    buf.extend_from_slice(&1u16.to_be_bytes());
+    buf.extend_from_slice(&TAG_SOFTWARE.to_be_bytes());
+    buf.extend_from_slice(&TYPE_ASCII.to_be_bytes());
+    buf.extend_from_slice(&3u32.to_be_bytes());
+    buf.extend_from_slice(b"cs\0\0");
+    buf.extend_from_slice(&0u32.to_be_bytes());
+
+    let tiff = Tiff::new(&buf).unwrap();
+    assert_eq!(tiff.ascii(tiff.ifd0().unwrap(), TAG_SOFTWARE).as_deref(), Some("cs"));
+    assert!(Tiff::new(b"II\0*").is_none());
+  }
+}
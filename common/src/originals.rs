@@ -0,0 +1,110 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::ORIGINALS_DIR_NAME;
+use crate::enums::OriginalsAction;
+use crate::pipeline::numbered;
+use crate::structs::MergedOption;
+
+/// Applies `mo.originals` to `images`, whose outputs were written and verified into `out_dir` by a run started at
+/// `started`.
+/// Moved and archived images go into `originals/<target folder name>` in `out_dir`, so they never end up in a layer
+/// folder of the target directory. Returns a message for every image that could not be handled; such images are left
+/// in place.
+pub fn dispose_originals(
+  images: &[PathBuf],
+  mo: &MergedOption,
+  out_dir: &Path,
+  started: DateTime<Local>,
+) -> Vec<String> {
+  if images.is_empty() {
+    return Vec::new();
+  }
+  let root = mo.target.canonicalize().unwrap_or_else(|_| mo.target.clone());
+  let store = out_dir.join(ORIGINALS_DIR_NAME).join(root.file_name().unwrap_or_default());
+  match mo.originals {
+    OriginalsAction::Keep => Vec::new(),
+    OriginalsAction::Move => images
+      .iter()
+      .filter_map(|f| {
+        let dst = free_path(store.join(relative(f, &root)));
+        dst
+          .parent()
+          .map_or(Ok(()), fs::create_dir_all)
+          .and_then(|_| fs::rename(f, &dst))
+          .err()
+          .map(|e| format!("Failed to move original '{}' to '{}': {}", f.display(), dst.display(), e))
+      })
+      .collect(),
+    OriginalsAction::Zip => {
+      let name =
+        format!("{}-{}.zip", root.file_name().unwrap_or_default().to_string_lossy(), started.format("%Y%m%d-%H%M%S"));
+      let archive = free_path(store.with_file_name(name));
+      match write_zip(&archive, images, &root) {
+        Ok(()) => images
+          .iter()
+          .filter_map(|f| {
+            fs::remove_file(f).err().map(|e| format!("Failed to delete archived original '{}': {}", f.display(), e))
+          })
+          .collect(),
+        Err(e) => {
+          let _ = fs::remove_file(&archive);
+          vec![format!("Failed to archive originals into '{}', they are kept: {}", archive.display(), e)]
+        },
+      }
+    },
+    OriginalsAction::Trash => match trash::delete_all(images) {
+      Ok(()) => Vec::new(),
+      Err(e) => vec![format!("Failed to send originals to the trash: {}", e)],
+    },
+  }
+}
+
+/// Writes `images` into a new, uncompressed archive, as screenshots are compressed already, then reads it back to check
+/// that every image made it.
+fn write_zip(archive: &Path, images: &[PathBuf], root: &Path) -> Result<(), String> {
+  archive.parent().map_or(Ok(()), fs::create_dir_all).map_err(|e| e.to_string())?;
+  let mut zip = ZipWriter::new(File::create_new(archive).map_err(|e| e.to_string())?);
+  for f in images {
+    let mut input = File::open(f).map_err(|e| format!("{}: {}", f.display(), e))?;
+    let size = input.metadata().map_err(|e| e.to_string())?.len();
+    let options =
+      SimpleFileOptions::default().compression_method(CompressionMethod::Stored).large_file(size >= u32::MAX as u64);
+    zip.start_file(entry_name(f, root), options).map_err(|e| e.to_string())?;
+    io::copy(&mut input, &mut zip).map_err(|e| format!("{}: {}", f.display(), e))?;
+  }
+  zip.finish().map_err(|e| e.to_string())?.sync_all().map_err(|e| e.to_string())?;
+
+  let mut zip = ZipArchive::new(File::open(archive).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+  for f in images {
+    let size = fs::metadata(f).map_err(|e| e.to_string())?.len();
+    let entry = zip.by_name(&entry_name(f, root)).map_err(|e| format!("{}: {}", f.display(), e))?;
+    if entry.size() != size {
+      return Err(format!("{}: archived {} of {} bytes", f.display(), entry.size(), size));
+    }
+  }
+  Ok(())
+}
+
+/// Path of `f` under `root`, or its file name if it is not under `root`.
+fn relative(f: &Path, root: &Path) -> PathBuf {
+  f.strip_prefix(root).map(Path::to_path_buf).unwrap_or_else(|_| f.file_name().unwrap_or_default().into())
+}
+
+/// Zip entries use '/' as separator on every platform.
+fn entry_name(f: &Path, root: &Path) -> String {
+  relative(f, root).components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+/// Returns `path`, or the first free `<stem>-<n>.<ext>` next to it if it exists.
+fn free_path(path: PathBuf) -> PathBuf {
+  if !path.exists() {
+    return path;
+  }
+  (1..).map(|n| numbered(&path, n)).find(|p| !p.exists()).unwrap()
+}
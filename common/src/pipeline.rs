@@ -0,0 +1,797 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::Instant;
+use std::{fs, thread};
+
+use chrono::{DateTime, Local};
+use image::imageops::{overlay, resize};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use libblur::FastBlurChannels::Channels4;
+use libblur::ThreadingPolicy::{Adaptive, Single};
+use libblur::{
+  BlurError, BlurImage, BlurImageMut, BoxBlurParameters, BufferStore, ConvolutionMode, EdgeMode, GaussianBlurParams,
+  ThreadingPolicy, box_blur, gaussian_blur,
+};
+use serde::Serialize;
+
+use crate::enums::{Anchor, ConflictPolicy, CropPosition, OriginalsAction, RedactStyle};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::metadata::open_image;
+use crate::originals::dispose_originals;
+use crate::report::{ImageReport, ImageStats, ImageStatus, RunReport, millis};
+use crate::structs::MergedOption;
+use crate::template::FilenameTemplate;
+
+#[derive(Clone, Debug)]
+pub enum ImageMsg {
+  Done {
+    filename: String,
+  },
+  Error {
+    text: String,
+  },
+  Finished,
+  Progress {
+    current: usize,
+    total: usize,
+    filename: String,
+  },
+  /// Left alone, as its output is up to date or taken by another file
+  Skipped {
+    filename: String,
+  },
+  /// Something to look at that did not stop the image, such as a renamed output. Sent after the `Done`, `Skipped` or
+  /// `Error` of the image it is about
+  Warning {
+    text: String,
+  },
+}
+
+/// Start of a run of [`process_image`]. Runs that continue an earlier one, like the batches of `cs watch`, count
+/// `{index}` on from it and get their own `{date}` and `{time}`.
+#[derive(Clone, Copy, Debug)]
+pub struct Run {
+  /// Time used for `{date}`, `{time}`, the report and the archive of originals
+  pub started: DateTime<Local>,
+  /// `{index}` of the first image
+  pub first_index: usize,
+  /// One of a series of runs, whose caller adds the report to that of the series with [`RunReport::append`] and saves
+  /// it, named by the start of the first run so it does not replace that of another series
+  pub batch: bool,
+}
+
+/// A single run starting now.
+impl Default for Run {
+  fn default() -> Self {
+    Run { started: Local::now(), first_index: 1, batch: false }
+  }
+}
+
+/// Receiver of the progress events emitted by [`process_image`].
+pub trait ProgressSink {
+  fn send(&self, msg: ImageMsg);
+}
+
+impl<F: Fn(ImageMsg)> ProgressSink for F {
+  fn send(&self, msg: ImageMsg) {
+    self(msg)
+  }
+}
+
+/// Converts every image in `images` according to `mo` and writes the results into `out_dir`.
+/// Images are converted by `mo.jobs` workers, but progress is reported to `sink` in the order of `images`, ending with
+/// [`ImageMsg::Finished`]. With `mo.incremental`, images whose output is up to date are skipped. A [`RunReport`] is
+/// written into `out_dir` at the end, and returned.
+pub fn process_image(
+  images: Vec<PathBuf>,
+  mo: &MergedOption,
+  out_dir: PathBuf,
+  run: Run,
+  sink: &impl ProgressSink,
+) -> RunReport {
+  let started = Instant::now();
+  let total = images.len();
+  let jobs = mo.jobs.clamp(1, total.max(1));
+  let next = AtomicUsize::new(0);
+  let (tx, rx) = mpsc::channel();
+  let mut manifest = mo.incremental.then(|| Manifest::load(mo, &out_dir));
+  let claims = claim_outputs(&images, mo, &out_dir, manifest.as_ref(), &run);
+  let mut entries = Vec::new();
+  let mut converted = Vec::new();
+  let mut reports = Vec::with_capacity(total);
+
+  thread::scope(|scope| {
+    for _ in 0..jobs {
+      let tx = tx.clone();
+      let (images, next, claims, manifest) = (&images, &next, &claims, &manifest);
+      scope.spawn(move || {
+        loop {
+          let i = next.fetch_add(1, Ordering::Relaxed);
+          if i >= total {
+            break;
+          }
+          let mut stats =
+            ImageStats { input_bytes: fs::metadata(&images[i]).ok().map(|m| m.len()), ..Default::default() };
+          let (result, entry) = match &claims[i] {
+            Claim::Write { dst, .. } => convert_or_skip(&images[i], mo, dst, manifest.as_ref(), &mut stats),
+            Claim::Skip(_) => (Ok(Outcome::Skipped), None),
+            Claim::Fail(text) => (Err(text.clone()), None),
+          };
+          if tx.send((i, (result, entry, stats))).is_err() {
+            break;
+          }
+        }
+      });
+    }
+    drop(tx);
+
+    // hold back results finished ahead of an earlier image
+    let mut pending = BTreeMap::new();
+    let mut current = 0;
+    for (i, result) in rx {
+      pending.insert(i, result);
+      while let Some((result, entry, stats)) = pending.remove(&current) {
+        let f = &images[current];
+        let filename = f.file_name().unwrap().to_string_lossy().to_string();
+        let conflict = claims[current].conflict();
+        let output = match &claims[current] {
+          Claim::Write { dst, .. } => Some(dst.clone()),
+          Claim::Skip(_) | Claim::Fail(_) => None,
+        };
+        reports.push(ImageReport {
+          input: f.clone(),
+          output,
+          status: match &result {
+            Ok(Outcome::Converted(_)) => ImageStatus::Converted,
+            Ok(Outcome::Skipped) => ImageStatus::Skipped,
+            Err(_) => ImageStatus::Failed,
+          },
+          error: result.as_ref().err().cloned(),
+          stats,
+        });
+        entries.push((current, entry));
+        current += 1;
+        sink.send(ImageMsg::Progress { current, total, filename: filename.clone() });
+        let mut unblurred = None;
+        match result {
+          Ok(Outcome::Converted(text)) => {
+            unblurred = text;
+            converted.push(f.clone());
+            sink.send(ImageMsg::Done { filename })
+          },
+          Ok(Outcome::Skipped) => sink.send(ImageMsg::Skipped { filename }),
+          // silently skip to next image
+          Err(text) => sink.send(ImageMsg::Error { text }),
+        }
+        // after the outcome, which replaces the progress line of the image in 'cs-gui'
+        for text in conflict.map(str::to_string).into_iter().chain(unblurred) {
+          sink.send(ImageMsg::Warning { text });
+        }
+      }
+    }
+  });
+
+  if let Some(manifest) = &mut manifest {
+    for (i, entry) in entries {
+      match entry {
+        Some(entry) => manifest.insert(&images[i], mo, entry),
+        None => manifest.remove(&images[i], mo),
+      }
+    }
+    if let Err(text) = manifest.save(mo, &out_dir) {
+      sink.send(ImageMsg::Error { text });
+    }
+  }
+
+  for text in dispose_originals(&converted, mo, &out_dir, run.started) {
+    sink.send(ImageMsg::Warning { text });
+  }
+
+  let report = RunReport::new(mo, reports, &run, started);
+  if !run.batch
+    && let Err(text) = report.save(&out_dir)
+  {
+    sink.send(ImageMsg::Warning { text });
+  }
+
+  sink.send(ImageMsg::Finished);
+  report
+}
+
+enum Outcome {
+  /// With the warning about blur regions that were left out, see [`ImagePlan::unblurred`]
+  Converted(Option<String>),
+  Skipped,
+}
+
+/// Output file of an image, decided before any image is converted.
+pub enum Claim {
+  /// Write to `dst`. `conflict` tells how a conflict over the natural output file was resolved
+  Write { dst: PathBuf, conflict: Option<String> },
+  /// Leave the image unconverted because of a conflict
+  Skip(String),
+  /// Fail the image because of a conflict
+  Fail(String),
+}
+
+impl Claim {
+  /// Returns the description of the conflict met by the image, if a conflict did not fail it.
+  pub fn conflict(&self) -> Option<&str> {
+    match self {
+      Claim::Write { conflict, .. } => conflict.as_deref(),
+      Claim::Skip(text) => Some(text),
+      Claim::Fail(_) => None,
+    }
+  }
+}
+
+/// Decides the output file of every image, in the order of `images`, resolving conflicts with `mo.output.on_conflict`.
+/// A file is taken if an earlier image of the batch claimed it, or if it exists and `manifest` does not record it as
+/// the output of the same image. Two images of a batch never overwrite each other, whatever the policy.
+pub fn claim_outputs(
+  images: &[PathBuf],
+  mo: &MergedOption,
+  out_dir: &Path,
+  manifest: Option<&Manifest>,
+  run: &Run,
+) -> Vec<Claim> {
+  let template = FilenameTemplate::parse(&mo.output.filename);
+  let mut claimed: HashMap<PathBuf, &Path> = HashMap::new();
+  let mut claims = Vec::with_capacity(images.len());
+  for (i, f) in images.iter().enumerate() {
+    let filename = f.file_name().unwrap().to_string_lossy();
+    let dst = match template
+      .as_ref()
+      .map_err(String::clone)
+      .and_then(|t| output_path(f, run.first_index + i, t, mo, out_dir, run.started))
+    {
+      Ok(dst) => dst,
+      Err(e) => {
+        claims.push(Claim::Fail(e));
+        continue;
+      },
+    };
+    let taken = |p: &Path, claimed: &HashMap<PathBuf, &Path>| {
+      claimed.contains_key(p) || (p.exists() && !manifest.is_some_and(|m| m.owns(f, mo, p)))
+    };
+    let claim = if !taken(&dst, &claimed) {
+      Claim::Write { dst, conflict: None }
+    } else {
+      let reason = match claimed.get(&dst) {
+        Some(other) => format!("'{}' is also the output of '{}'", dst.display(), other.display()),
+        None => format!("'{}' already exists", dst.display()),
+      };
+      match mo.output.on_conflict {
+        ConflictPolicy::Overwrite if !claimed.contains_key(&dst) => Claim::Write { dst, conflict: None },
+        ConflictPolicy::Skip => Claim::Skip(format!("{}, skipped {}", reason, filename)),
+        ConflictPolicy::Rename => {
+          let renamed = (1..).map(|n| numbered(&dst, n)).find(|p| !taken(p, &claimed)).unwrap();
+          let conflict = format!("{}, writing {} to '{}'", reason, filename, renamed.display());
+          Claim::Write { dst: renamed, conflict: Some(conflict) }
+        },
+        ConflictPolicy::Overwrite | ConflictPolicy::Error => Claim::Fail(format!("{}: {}", reason, filename)),
+      }
+    };
+    if let Claim::Write { dst, .. } = &claim {
+      claimed.insert(dst.clone(), f);
+    }
+    claims.push(claim);
+  }
+  claims
+}
+
+/// Returns `<stem>-<n>.<ext>` next to `path`.
+pub(crate) fn numbered(path: &Path, n: usize) -> PathBuf {
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  match path.extension() {
+    Some(ext) => path.with_file_name(format!("{}-{}.{}", stem, n, ext.to_string_lossy())),
+    None => path.with_file_name(format!("{}-{}", stem, n)),
+  }
+}
+
+/// Converts `f` into `dst` unless `manifest` has it up to date. Also returns the manifest entry of `f` if it now has
+/// an output.
+fn convert_or_skip(
+  f: &Path,
+  mo: &MergedOption,
+  dst: &Path,
+  manifest: Option<&Manifest>,
+  stats: &mut ImageStats,
+) -> (Result<Outcome, String>, Option<ManifestEntry>) {
+  let Some(manifest) = manifest else {
+    return (convert_image(f, mo, dst, stats).map(|plan| Outcome::Converted(plan.unblurred)), None);
+  };
+  if let Some(entry) = manifest.check(f, mo, dst) {
+    return (Ok(Outcome::Skipped), Some(entry));
+  }
+  match convert_image(f, mo, dst, stats) {
+    Ok(plan) => (Ok(Outcome::Converted(plan.unblurred)), ManifestEntry::read(f, dst).ok()),
+    Err(e) => (Err(e), None),
+  }
+}
+
+/// What [`convert_image`] does to an image, worked out from its size alone.
+#[derive(Clone, Debug, Serialize)]
+pub struct ImagePlan {
+  pub input: PathBuf,
+  pub width: u32,
+  pub height: u32,
+  /// Redacted areas, in pixels of the input image
+  pub blur: Vec<Redaction>,
+  /// Why blur regions were left out, only with `mo.allow_unblurred` as the image fails otherwise
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub unblurred: Option<String>,
+  /// Kept area as `[x, y, width, height]`, in pixels of the input image
+  pub crop: [u32; 4],
+  /// Size after resizing, or `None` if the cropped image is kept as is
+  pub resize: Option<[u32; 2]>,
+  pub output: PathBuf,
+  /// How a conflict over the output file was resolved, see [`Claim::conflict`]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub conflict: Option<String>,
+}
+
+/// A blur region resolved to pixels of an image.
+#[derive(Clone, Debug, Serialize)]
+pub struct Redaction {
+  pub area: [u32; 4],
+  pub style: RedactStyle,
+  pub strength: u32,
+  pub color: [u8; 3],
+}
+
+/// Plans the conversion of `f` into `dst` by reading its size, without decoding or writing anything.
+/// Only `CropPosition::Auto` decodes the image, to find its dark bars.
+pub fn plan_image(f: &Path, mo: &MergedOption, dst: &Path) -> Result<ImagePlan, String> {
+  if mo.crop_pos == CropPosition::Auto {
+    let img = image::open(f).map_err(|e| format!("Failed to open '{}': {}", f.display(), e))?;
+    return plan(f, &img, mo, dst);
+  }
+  let (w, h) = image::image_dimensions(f).map_err(|e| format!("Failed to open '{}': {}", f.display(), e))?;
+  plan_sized(f, w, h, None, mo, dst)
+}
+
+fn plan(f: &Path, img: &DynamicImage, mo: &MergedOption, dst: &Path) -> Result<ImagePlan, String> {
+  let (w, h) = img.dimensions();
+  plan_sized(f, w, h, Some(img), mo, dst)
+}
+
+/// `img` is only read by `CropPosition::Auto`.
+fn plan_sized(
+  f: &Path,
+  w: u32,
+  h: u32,
+  img: Option<&DynamicImage>,
+  mo: &MergedOption,
+  dst: &Path,
+) -> Result<ImagePlan, String> {
+  let filename = f.file_name().unwrap().to_string_lossy().to_string();
+
+  // auto crops are found in the pixels, not scaled from `reference`
+  if !matches!(mo.crop_pos, CropPosition::Full | CropPosition::Auto) {
+    if mo.width_from > 0 && w != mo.width_from {
+      return Err(format!("Expected width is {} but got {}: {}", mo.width_from, w, filename));
+    }
+    if !mo.matches_reference(w, h) {
+      let [ref_w, ref_h] = mo.reference;
+      return Err(format!("Expected aspect ratio of {}x{} but got {}x{}: {}", ref_w, ref_h, w, h, filename));
+    }
+  }
+
+  // blur
+  // regions are left out of images they were not set for, or that they do not fit into
+  let mut blur = Vec::new();
+  let applies = mo.should_blur(w, h);
+  for region in &mo.blur {
+    if let Some(area) = mo.region_area(region, w, h).filter(|_| applies) {
+      let strength = mo.scale(region.strength(), w).max(1);
+      blur.push(Redaction { area, style: region.style, strength, color: region.color });
+    }
+  }
+  let mut unblurred = None;
+  if blur.len() < mo.blur.len() {
+    let text = format!(
+      "{} of {} blur regions cannot be applied to an image of {}x{}",
+      mo.blur.len() - blur.len(),
+      mo.blur.len(),
+      w,
+      h
+    );
+    if !mo.allow_unblurred {
+      return Err(format!("{}, use '--allow-unblurred' to convert it anyway: {}", text, filename));
+    }
+    unblurred = Some(format!("{}, converted without them: {}", text, filename));
+  }
+
+  // crop
+  let crop_height = mo.scale(mo.crop_height, w);
+  let crop = match mo.crop_pos {
+    CropPosition::Full => [0, 0, w, h],
+    CropPosition::Auto => img.map_or([0, 0, w, h], |img| content_area(img, mo.auto_crop_threshold)),
+    CropPosition::Rect(area) => match mo.area(area, Anchor::TopLeft, w, h) {
+      Some(crop) if crop[2] > 0 && crop[3] > 0 => crop,
+      _ => {
+        let area = area.map(|c| c.to_string()).join(",");
+        return Err(format!("Crop area {} does not fit in an image of {}x{}: {}", area, w, h, filename));
+      },
+    },
+    _ if crop_height > h => {
+      return Err(format!("Crop height {} is larger than image height {}: {}", crop_height, h, filename));
+    },
+    CropPosition::Top => [0, 0, w, crop_height],
+    CropPosition::Bottom => [0, h - crop_height, w, crop_height],
+    CropPosition::Center => [0, (h - crop_height) / 2, w, crop_height],
+    CropPosition::Custom { y } => {
+      let y = mo.scale(y, w);
+      if y + crop_height > h {
+        return Err(format!("Crop rows {} to {} exceed image height {}: {}", y, y + crop_height, h, filename));
+      }
+      [0, y, w, crop_height]
+    },
+  };
+  let crop = match mo.crop_aspect {
+    Some(ratio) => ratio.fit(crop, mo.crop_anchor),
+    None => crop,
+  };
+
+  // resize
+  let resize = match mo.resize {
+    Some(mode) => mode.size(crop[2], crop[3]).map_err(|e| format!("{}: {}", e, filename))?,
+    // a crop narrower than the image shrinks by as much as the whole image would
+    None => mo.should_resize(w).then(|| {
+      let ratio = crop[3] as f32 / crop[2] as f32; // using (maybe) cropped img value!
+      let width =
+        if crop[2] == w { mo.width_to } else { (crop[2] as u64 * mo.width_to as u64 / w as u64).max(1) as u32 };
+      [width, ((width as f32 * ratio) as u32).max(1)]
+    }),
+  };
+
+  Ok(ImagePlan {
+    input: f.to_path_buf(),
+    width: w,
+    height: h,
+    blur,
+    unblurred,
+    crop,
+    resize,
+    output: dst.to_path_buf(),
+    conflict: None,
+  })
+}
+
+/// Returns where `f`, the `index`th image of the batch, is written unless it conflicts with another file.
+fn output_path(
+  f: &Path,
+  index: usize,
+  template: &FilenameTemplate,
+  mo: &MergedOption,
+  out_dir: &Path,
+  started: DateTime<Local>,
+) -> Result<PathBuf, String> {
+  let dst_dir = if mo.scan.mirror { mirror_dir(f, mo, out_dir) } else { out_dir.to_path_buf() };
+  Ok(dst_dir.join(format!("{}.{}", template.render(f, index, started, mo)?, mo.output.encoder().extension())))
+}
+
+/// Converts a single image according to `mo` and writes the result to `dst`, returning the plan it followed. Sizes and
+/// stage times go into `stats`.
+pub fn convert_image(f: &Path, mo: &MergedOption, dst: &Path, stats: &mut ImageStats) -> Result<ImagePlan, String> {
+  // load image
+  let t = Instant::now();
+  let (mut img, metadata) = open_image(f, mo.output.encoder().metadata())?;
+  stats.stages.decode_ms = millis(t.elapsed());
+
+  #[cfg(debug_assertions)]
+  eprintln!("▶ [DEBUG] game={}, layer={}, width_to={}", mo.game, mo.layer, mo.width_to); // this will only be displayed in DEBUG
+
+  let (w, h) = img.dimensions();
+  (stats.input_width, stats.input_height) = (Some(w), Some(h));
+  let plan = plan(f, &img, mo, dst)?;
+
+  // blur
+  // workers already occupy every core, so blur on a single thread unless there is only one worker
+  let t = Instant::now();
+  let policy = if mo.jobs > 1 { Single } else { Adaptive };
+  for redaction in &plan.blur {
+    redact_area(&mut img, redaction, policy).map_err(|e| format!("Failed to blur '{}': {}", f.display(), e))?;
+  }
+  stats.stages.blur_ms = millis(t.elapsed());
+
+  // crop
+  let t = Instant::now();
+  let [x, y, cw, ch] = plan.crop;
+  if plan.crop != [0, 0, w, h] {
+    img = img.crop_imm(x, y, cw, ch);
+  }
+  stats.stages.crop_ms = millis(t.elapsed());
+
+  // resize
+  let t = Instant::now();
+  if let Some([rw, rh]) = plan.resize {
+    img = DynamicImage::ImageRgba8(resize(&img, rw, rh, mo.resize_filter.into()))
+  }
+  stats.stages.resize_ms = millis(t.elapsed());
+  (stats.output_width, stats.output_height) = (Some(img.width()), Some(img.height()));
+
+  // encode with the selected output format
+  let t = Instant::now();
+  let buf =
+    mo.output.encoder().encode(&img, &metadata).map_err(|e| format!("Failed to encode '{}': {}", f.display(), e))?;
+  stats.stages.encode_ms = millis(t.elapsed());
+  stats.output_bytes = Some(buf.len() as u64);
+
+  // save
+  let t = Instant::now();
+  let result = write_output(&buf, mo, dst);
+  stats.stages.write_ms = millis(t.elapsed());
+  result.map(|_| plan)
+}
+
+/// Writes `buf` to `dst`, and reads it back if the original is about to go.
+fn write_output(buf: &[u8], mo: &MergedOption, dst: &Path) -> Result<(), String> {
+  if let Some(dst_dir) = dst.parent() {
+    fs::create_dir_all(dst_dir).map_err(|e| format!("Failed to create directory '{}': {}", dst_dir.display(), e))?;
+  }
+  let write_err = |e: io::Error| format!("Failed to write '{}': {}", dst.display(), e);
+  let mut file = File::create(dst).map_err(write_err)?;
+  file.write_all(buf).map_err(write_err)?;
+  if mo.originals == OriginalsAction::Keep {
+    return Ok(());
+  }
+
+  // verify, as the original is about to go
+  file.sync_all().map_err(write_err)?;
+  drop(file);
+  match fs::read(dst) {
+    Ok(written) if written == buf => Ok(()),
+    Ok(_) => Err(format!("Failed to verify '{}': it differs from the encoded image", dst.display())),
+    Err(e) => Err(format!("Failed to verify '{}': {}", dst.display(), e)),
+  }
+}
+
+/// Returns the `[x, y, width, height]` of `img` inside the rows and columns along its edges where nearly every pixel
+/// has no channel above `threshold`. An image that is dark all over is kept whole.
+fn content_area(img: &DynamicImage, threshold: u8) -> [u32; 4] {
+  let img = img.to_rgb8();
+  let (w, h) = img.dimensions();
+  // a line with up to 1% of brighter pixels, such as noise or a logo in the bar, still counts as a bar
+  let is_bar = |pixels: &mut dyn Iterator<Item = &image::Rgb<u8>>, len: u32| {
+    let bright = pixels.filter(|p| p.0.iter().any(|&c| c > threshold)).count() as u32;
+    bright * 100 <= len
+  };
+  let row_is_bar = |y: u32| is_bar(&mut (0..w).map(|x| img.get_pixel(x, y)), w);
+  let Some(top) = (0..h).find(|&y| !row_is_bar(y)) else {
+    return [0, 0, w, h];
+  };
+  let bottom = (top..h).rev().find(|&y| !row_is_bar(y)).unwrap_or(top) + 1;
+  let col_is_bar = |x: u32| is_bar(&mut (top..bottom).map(|y| img.get_pixel(x, y)), bottom - top);
+  let left = (0..w).find(|&x| !col_is_bar(x)).unwrap_or(0);
+  let right = (left..w).rev().find(|&x| !col_is_bar(x)).unwrap_or(left) + 1;
+  [left, top, right - left, bottom - top]
+}
+
+/// Returns the directory under `out_dir` that matches the directory of `f` under `mo.target`.
+fn mirror_dir(f: &Path, mo: &MergedOption, out_dir: &Path) -> PathBuf {
+  let root = mo.target.canonicalize().unwrap_or_else(|_| mo.target.clone());
+  match f.parent().and_then(|p| p.strip_prefix(&root).ok()) {
+    Some(rel) => out_dir.join(rel),
+    None => out_dir.to_path_buf(),
+  }
+}
+
+fn redact_area(img: &mut DynamicImage, redaction: &Redaction, policy: ThreadingPolicy) -> Result<(), String> {
+  let [x, y, bw, bh] = redaction.area;
+  if bw == 0 || bh == 0 {
+    return Ok(());
+  }
+  let strength = redaction.strength;
+  let src = img.crop_imm(x, y, bw, bh).to_rgba8();
+  let patch = match redaction.style {
+    RedactStyle::Box => blur_patch(&src, |s, d| box_blur(s, d, BoxBlurParameters::new(strength | 1), policy))?,
+    RedactStyle::Gaussian => blur_patch(&src, |s, d| {
+      let params = GaussianBlurParams::new_from_kernel(strength as f64);
+      gaussian_blur(s, d, params, EdgeMode::Clamp, policy, ConvolutionMode::FixedPoint)
+    })?,
+    RedactStyle::Pixelate => pixelate_patch(&src, strength),
+    RedactStyle::Solid => {
+      let [r, g, b] = redaction.color;
+      RgbaImage::from_pixel(bw, bh, Rgba([r, g, b, 255]))
+    },
+    RedactStyle::Noise => noise_patch(bw, bh, strength, (x as u64) << 32 | y as u64),
+  };
+  overlay(img, &patch, x.into(), y.into());
+  Ok(())
+}
+
+fn blur_patch(
+  src: &RgbaImage,
+  blur: impl FnOnce(&BlurImage<u8>, &mut BlurImageMut<u8>) -> Result<(), BlurError>,
+) -> Result<RgbaImage, String> {
+  let (bw, bh) = src.dimensions();
+  let src = BlurImage::borrow(src.as_raw(), bw, bh, Channels4);
+  let mut dst = BlurImageMut {
+    data: BufferStore::Owned(vec![0u8; (bw * bh * 4) as usize]),
+    width: bw,
+    height: bh,
+    stride: bw * 4,
+    channels: Channels4,
+  };
+  blur(&src, &mut dst).map_err(|e| e.to_string())?;
+  let buf = dst.data.borrow();
+  Ok(ImageBuffer::from_raw(bw, bh, buf.to_vec()).unwrap())
+}
+
+/// Replaces every `block`x`block` cell with its average color.
+fn pixelate_patch(src: &RgbaImage, block: u32) -> RgbaImage {
+  let (bw, bh) = src.dimensions();
+  let mut dst = RgbaImage::new(bw, bh);
+  for by in (0..bh).step_by(block as usize) {
+    for bx in (0..bw).step_by(block as usize) {
+      let (cw, ch) = (block.min(bw - bx), block.min(bh - by));
+      let mut sum = [0u64; 4];
+      for py in by..by + ch {
+        for px in bx..bx + cw {
+          for (acc, v) in sum.iter_mut().zip(src.get_pixel(px, py).0) {
+            *acc += v as u64;
+          }
+        }
+      }
+      let n = (cw * ch) as u64;
+      let avg = Rgba(sum.map(|acc| (acc / n) as u8));
+      for py in by..by + ch {
+        for px in bx..bx + cw {
+          dst.put_pixel(px, py, avg);
+        }
+      }
+    }
+  }
+  dst
+}
+
+/// Fills with opaque gray noise in `grain`x`grain` cells. Nothing of the source is kept.
+fn noise_patch(bw: u32, bh: u32, grain: u32, seed: u64) -> RgbaImage {
+  // splitmix64, so the same region gets the same noise on every run
+  let mut state = seed;
+  let mut next = || {
+    state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)) as u8
+  };
+  let cells_w = bw.div_ceil(grain);
+  let cells: Vec<u8> = (0..cells_w * bh.div_ceil(grain)).map(|_| next()).collect();
+  RgbaImage::from_fn(bw, bh, |px, py| {
+    let v = cells[((py / grain) * cells_w + px / grain) as usize];
+    Rgba([v, v, v, 255])
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::{BlurRegion, Coord};
+
+  /// Returns an empty directory of its own for every test.
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cs-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn options(dir: &Path, filename: &str, on_conflict: ConflictPolicy) -> MergedOption {
+    let mut mo = MergedOption::for_tests(dir);
+    mo.output.filename = filename.into();
+    mo.output.on_conflict = on_conflict;
+    mo
+  }
+
+  fn claim(dir: &Path, mo: &MergedOption, names: &[&str]) -> Vec<Claim> {
+    let images: Vec<PathBuf> = names.iter().map(|name| dir.join(name)).collect();
+    claim_outputs(&images, mo, dir, None, &Run::default())
+  }
+
+  fn written(claim: &Claim) -> (&Path, Option<&str>) {
+    match claim {
+      Claim::Write { dst, conflict } => (dst, conflict.as_deref()),
+      _ => panic!("expected a write, got {:?}", claim.conflict()),
+    }
+  }
+
+  #[test]
+  fn claims_free_outputs() {
+    let dir = temp_dir("claim-free");
+    for policy in [ConflictPolicy::Skip, ConflictPolicy::Overwrite, ConflictPolicy::Rename, ConflictPolicy::Error] {
+      let claims = claim(&dir, &options(&dir, "{stem}", policy), &["a.png", "b.png"]);
+      assert_eq!(written(&claims[0]), (dir.join("a.webp").as_path(), None));
+      assert_eq!(written(&claims[1]), (dir.join("b.webp").as_path(), None));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn resolves_existing_output() {
+    let dir = temp_dir("claim-existing");
+    fs::write(dir.join("a.webp"), b"").unwrap();
+    fs::write(dir.join("a-1.webp"), b"").unwrap();
+
+    let claims = claim(&dir, &options(&dir, "{stem}", ConflictPolicy::Skip), &["a.png"]);
+    assert!(matches!(&claims[0], Claim::Skip(text) if text.contains("already exists") && text.ends_with("a.png")));
+
+    let claims = claim(&dir, &options(&dir, "{stem}", ConflictPolicy::Overwrite), &["a.png"]);
+    assert_eq!(written(&claims[0]), (dir.join("a.webp").as_path(), None));
+
+    let claims = claim(&dir, &options(&dir, "{stem}", ConflictPolicy::Rename), &["a.png"]);
+    let (dst, conflict) = written(&claims[0]);
+    assert_eq!(dst, dir.join("a-2.webp"));
+    assert!(conflict.unwrap().contains("already exists"));
+
+    let claims = claim(&dir, &options(&dir, "{stem}", ConflictPolicy::Error), &["a.png"]);
+    assert!(matches!(&claims[0], Claim::Fail(text) if text.contains("already exists")));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn never_writes_two_images_to_one_file() {
+    let dir = temp_dir("claim-batch");
+    let names = ["a.png", "b.png", "c.png"];
+
+    let claims = claim(&dir, &options(&dir, "{layer}", ConflictPolicy::Skip), &names);
+    assert_eq!(written(&claims[0]), (dir.join("full.webp").as_path(), None));
+    assert!(matches!(&claims[1], Claim::Skip(text) if text.contains("also the output of")));
+    assert!(matches!(&claims[2], Claim::Skip(_)));
+
+    // overwriting is only for files of earlier runs
+    let claims = claim(&dir, &options(&dir, "{layer}", ConflictPolicy::Overwrite), &names);
+    assert_eq!(written(&claims[0]), (dir.join("full.webp").as_path(), None));
+    assert!(matches!(&claims[1], Claim::Fail(text) if text.contains("also the output of")));
+
+    let claims = claim(&dir, &options(&dir, "{layer}", ConflictPolicy::Rename), &names);
+    assert_eq!(written(&claims[0]).0, dir.join("full.webp"));
+    assert_eq!(written(&claims[1]).0, dir.join("full-1.webp"));
+    assert_eq!(written(&claims[2]).0, dir.join("full-2.webp"));
+
+    let claims = claim(&dir, &options(&dir, "{layer}", ConflictPolicy::Error), &names);
+    assert!(matches!(&claims[1], Claim::Fail(_)));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn counts_index_from_the_run() {
+    let dir = temp_dir("claim-index");
+    let images = [dir.join("a.png"), dir.join("b.png")];
+    let run = Run { first_index: 7, ..Run::default() };
+    let claims = claim_outputs(&images, &options(&dir, "{index:03}", ConflictPolicy::Error), &dir, None, &run);
+    assert_eq!(written(&claims[0]).0, dir.join("007.webp"));
+    assert_eq!(written(&claims[1]).0, dir.join("008.webp"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn blur_regions_scaled_to_nothing_are_unblurred() {
+    let mut mo = MergedOption { reference: [1920, 1080], ..MergedOption::for_tests(Path::new("shots")) };
+    mo.blur = vec![BlurRegion::from([Coord::Px(40), Coord::Px(1054), Coord::Px(330), Coord::Px(22)])];
+    let (f, dst) = (Path::new("shots/tiny.png"), Path::new("shots/converted/tiny.webp"));
+
+    let err = plan_sized(f, 32, 18, None, &mo, dst).unwrap_err();
+    assert!(err.starts_with("1 of 1 blur regions cannot be applied to an image of 32x18"), "{}", err);
+
+    mo.allow_unblurred = true;
+    let plan = plan_sized(f, 32, 18, None, &mo, dst).unwrap();
+    assert!(plan.blur.is_empty());
+    assert!(plan.unblurred.unwrap().contains("converted without them"));
+    assert_eq!(plan_sized(f, 1920, 1080, None, &mo, dst).unwrap().blur[0].area, [40, 1054, 330, 22]);
+  }
+
+  #[test]
+  fn fails_every_image_on_a_bad_template() {
+    let dir = temp_dir("claim-template");
+    let claims = claim(&dir, &options(&dir, "{nope}", ConflictPolicy::Overwrite), &["a.png"]);
+    assert!(matches!(&claims[0], Claim::Fail(text) if text.contains("unknown placeholder")));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
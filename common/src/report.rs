@@ -0,0 +1,279 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::layer_file;
+use crate::pipeline::Run;
+use crate::structs::MergedOption;
+
+/// Record of a run of [`crate::pipeline::process_image`], written into the output directory as JSON and CSV.
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+  pub started: String,
+  pub elapsed_ms: f64,
+  pub totals: Totals,
+  /// Effective options of the run
+  pub options: MergedOption,
+  pub images: Vec<ImageReport>,
+  /// Start time in the file name, for runs that must not replace the report of the one before
+  #[serde(skip)]
+  stamp: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStatus {
+  Converted,
+  Skipped,
+  Failed,
+}
+
+impl ImageStatus {
+  fn name(self) -> &'static str {
+    match self {
+      ImageStatus::Converted => "converted",
+      ImageStatus::Skipped => "skipped",
+      ImageStatus::Failed => "failed",
+    }
+  }
+}
+
+/// What happened to one input of a run.
+#[derive(Clone, Debug, Serialize)]
+pub struct ImageReport {
+  pub input: PathBuf,
+  pub output: Option<PathBuf>,
+  pub status: ImageStatus,
+  pub error: Option<String>,
+  #[serde(flatten)]
+  pub stats: ImageStats,
+}
+
+/// Sizes and timings of an image, filled in as far as its conversion got.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ImageStats {
+  pub input_width: Option<u32>,
+  pub input_height: Option<u32>,
+  pub output_width: Option<u32>,
+  pub output_height: Option<u32>,
+  pub input_bytes: Option<u64>,
+  pub output_bytes: Option<u64>,
+  pub stages: StageTimes,
+}
+
+/// Time spent in every stage of a conversion, in milliseconds. Writing includes verifying the output.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct StageTimes {
+  pub decode_ms: f64,
+  pub blur_ms: f64,
+  pub crop_ms: f64,
+  pub resize_ms: f64,
+  pub encode_ms: f64,
+  pub write_ms: f64,
+}
+
+/// Sums over the images of a run. Bytes only count converted images.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Totals {
+  pub images: usize,
+  pub converted: usize,
+  pub skipped: usize,
+  pub failed: usize,
+  pub input_bytes: u64,
+  pub output_bytes: u64,
+  pub stages: StageTimes,
+}
+
+const CSV_HEADER: &str = "input,output,status,error,input_width,input_height,output_width,output_height,input_bytes,\
+                          output_bytes,decode_ms,blur_ms,crop_ms,resize_ms,encode_ms,write_ms";
+
+impl RunReport {
+  /// Sums up `images`, converted by `run` whose conversion began at `timer`.
+  pub fn new(mo: &MergedOption, images: Vec<ImageReport>, run: &Run, timer: Instant) -> RunReport {
+    RunReport {
+      started: run.started.to_rfc3339(),
+      elapsed_ms: millis(timer.elapsed()),
+      totals: Totals::sum(&images),
+      options: mo.clone(),
+      images,
+      stamp: run.batch.then(|| run.started.format("%Y%m%d-%H%M%S-%3f").to_string()),
+    }
+  }
+
+  /// Adds the images and time of `batch`, a later run with the same options.
+  pub fn append(&mut self, batch: RunReport) {
+    self.elapsed_ms = ((self.elapsed_ms + batch.elapsed_ms) * 1000.0).round() / 1000.0;
+    self.images.extend(batch.images);
+    self.totals = Totals::sum(&self.images);
+  }
+
+  /// Returns the path of the report in `out_dir`, with `ext` as extension, see [`layer_file`].
+  pub fn path(&self, out_dir: &Path, ext: &str) -> PathBuf {
+    match &self.stamp {
+      Some(stamp) => layer_file(&self.options, out_dir, "cs-report", &format!("{}.{}", stamp, ext)),
+      None => layer_file(&self.options, out_dir, "cs-report", ext),
+    }
+  }
+
+  /// Writes the report as `cs-report.<game>.<layer>.json` and `.csv` into `out_dir`, replacing those of earlier runs.
+  /// The report of a series of batch runs puts the start of the first before the extension instead.
+  pub fn save(&self, out_dir: &Path) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(self).map_err(|e| format!("Failed to serialize report: {}", e))?;
+    fs::create_dir_all(out_dir).map_err(|e| format!("Failed to create directory '{}': {}", out_dir.display(), e))?;
+    for (ext, buf) in [("json", json), ("csv", self.to_csv().into_bytes())] {
+      let path = self.path(out_dir, ext);
+      fs::write(&path, buf).map_err(|e| format!("Failed to write report '{}': {}", path.display(), e))?;
+    }
+    Ok(())
+  }
+
+  /// One row per image and a `total` row, then the effective options as `option,value` rows.
+  fn to_csv(&self) -> String {
+    let mut csv = String::new();
+    csv.push_str(CSV_HEADER);
+    csv.push('\n');
+    for image in &self.images {
+      let s = &image.stats;
+      let t = &s.stages;
+      let row = [
+        image.input.display().to_string(),
+        image.output.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+        image.status.name().to_string(),
+        image.error.clone().unwrap_or_default(),
+        opt(s.input_width),
+        opt(s.input_height),
+        opt(s.output_width),
+        opt(s.output_height),
+        opt(s.input_bytes),
+        opt(s.output_bytes),
+        t.decode_ms.to_string(),
+        t.blur_ms.to_string(),
+        t.crop_ms.to_string(),
+        t.resize_ms.to_string(),
+        t.encode_ms.to_string(),
+        t.write_ms.to_string(),
+      ];
+      push_row(&mut csv, &row);
+    }
+    let (totals, t) = (&self.totals, &self.totals.stages);
+    let summary = format!(
+      "{} images: {} converted, {} skipped, {} failed in {} ms",
+      totals.images, totals.converted, totals.skipped, totals.failed, self.elapsed_ms
+    );
+    let row = [
+      "total".to_string(),
+      String::new(),
+      String::new(),
+      summary,
+      String::new(),
+      String::new(),
+      String::new(),
+      String::new(),
+      totals.input_bytes.to_string(),
+      totals.output_bytes.to_string(),
+      t.decode_ms.to_string(),
+      t.blur_ms.to_string(),
+      t.crop_ms.to_string(),
+      t.resize_ms.to_string(),
+      t.encode_ms.to_string(),
+      t.write_ms.to_string(),
+    ];
+    push_row(&mut csv, &row);
+
+    csv.push_str("\noption,value\n");
+    let mut options = Vec::new();
+    flatten("", &serde_json::to_value(&self.options).unwrap_or(Value::Null), &mut options);
+    for (key, value) in options {
+      push_row(&mut csv, &[key, value]);
+    }
+    csv
+  }
+}
+
+impl Totals {
+  fn sum(images: &[ImageReport]) -> Totals {
+    let mut totals = Totals { images: images.len(), ..Default::default() };
+    for image in images {
+      match image.status {
+        ImageStatus::Converted => {
+          totals.converted += 1;
+          totals.input_bytes += image.stats.input_bytes.unwrap_or(0);
+          totals.output_bytes += image.stats.output_bytes.unwrap_or(0);
+        },
+        ImageStatus::Skipped => totals.skipped += 1,
+        ImageStatus::Failed => totals.failed += 1,
+      }
+      totals.stages.add(&image.stats.stages);
+    }
+    totals.stages.round();
+    totals
+  }
+}
+
+impl StageTimes {
+  fn add(&mut self, other: &StageTimes) {
+    self.decode_ms += other.decode_ms;
+    self.blur_ms += other.blur_ms;
+    self.crop_ms += other.crop_ms;
+    self.resize_ms += other.resize_ms;
+    self.encode_ms += other.encode_ms;
+    self.write_ms += other.write_ms;
+  }
+
+  /// Drops the noise that summing leaves below a microsecond.
+  fn round(&mut self) {
+    for v in [
+      &mut self.decode_ms,
+      &mut self.blur_ms,
+      &mut self.crop_ms,
+      &mut self.resize_ms,
+      &mut self.encode_ms,
+      &mut self.write_ms,
+    ] {
+      *v = (*v * 1000.0).round() / 1000.0;
+    }
+  }
+}
+
+/// Milliseconds of `d`, to the microsecond.
+pub fn millis(d: Duration) -> f64 {
+  d.as_micros() as f64 / 1000.0
+}
+
+fn opt<T: ToString>(v: Option<T>) -> String {
+  v.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Appends a row, quoting fields as RFC 4180 does.
+fn push_row(csv: &mut String, fields: &[String]) {
+  for (i, field) in fields.iter().enumerate() {
+    if i > 0 {
+      csv.push(',');
+    }
+    if field.contains([',', '"', '\n', '\r']) {
+      let _ = write!(csv, "\"{}\"", field.replace('"', "\"\""));
+    } else {
+      csv.push_str(field);
+    }
+  }
+  csv.push('\n');
+}
+
+/// Flattens nested tables into dotted keys. Arrays are kept as JSON.
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map {
+        let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        flatten(&key, value, out);
+      }
+    },
+    Value::String(s) => out.push((prefix.to_string(), s.clone())),
+    Value::Null => out.push((prefix.to_string(), String::new())),
+    value => out.push((prefix.to_string(), value.to_string())),
+  }
+}
@@ -1,36 +1,693 @@
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
 
-use crate::enums::{CropPosition, Game, Operation};
+use crate::enums::{
+  Anchor, ConflictPolicy, CropPosition, ExifField, OriginalsAction, OutputFormat, PngCompression, PngFilter,
+  RedactStyle, ResizeFilter, ResizeMode, WebPImageHint, WebPMode,
+};
+use crate::template::FilenameTemplate;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 // pub is required for properties
 pub struct MergedOption {
-  pub operation: Operation,
-  pub game: Game,
-  pub blur: Vec<[u32; 4]>,
+  /// Name of the `[game.<name>]` table in `cs.toml`
+  pub game: String,
+  /// Name of the `[game.<game>.<name>]` table in `cs.toml`
+  pub layer: String,
+  /// Convert images that not every blur region can be applied to, instead of failing them
+  pub allow_unblurred: bool,
+  /// Brightest value of a channel that `CropPosition::Auto` takes as part of a dark bar
+  pub auto_crop_threshold: u8,
+  pub blur: Vec<BlurRegion>,
+  /// Where the `crop_aspect` area sits in the area kept by `crop_pos`
+  pub crop_anchor: Anchor,
+  /// Keep the largest area of this ratio within the area kept by `crop_pos`
+  pub crop_aspect: Option<AspectRatio>,
   pub crop_height: u32,
   pub crop_pos: CropPosition,
+  /// Skip images whose output is up to date, see [`crate::manifest::Manifest`]
+  pub incremental: bool,
+  pub jobs: usize,
+  /// What happens to images once their output is written and verified
+  pub originals: OriginalsAction,
+  pub output: OutputOption,
+  /// Resolution that `blur` and `crop_height` are given in, as `[width, height]`
+  pub reference: [u32; 2],
+  /// How the cropped image is resized, or by `width_from` and `width_to` if `None`
+  pub resize: Option<ResizeMode>,
+  pub resize_filter: ResizeFilter,
   pub save_at_parent: bool,
+  pub scan: ScanOption,
   pub target: PathBuf,
   pub width_from: u32,
   pub width_to: u32,
 }
 
 impl MergedOption {
-  pub fn should_blur(&self, img_width: u32) -> bool {
-    if self.game == Game::None {
+  pub fn should_blur(&self, img_width: u32, img_height: u32) -> bool {
+    !self.blur.is_empty()
+      && (self.width_from == 0 || img_width == self.width_from)
+      && self.matches_reference(img_width, img_height)
+  }
+
+  pub fn should_resize(&self, img_width: u32) -> bool {
+    if self.width_to == 0 {
       return false;
     }
-    img_width == self.width_from && !self.blur.is_empty()
+    match self.crop_pos {
+      CropPosition::Full => img_width > self.width_to,
+      _ if self.width_from > 0 => img_width == self.width_from,
+      _ => img_width > self.width_to,
+    }
   }
 
-  pub fn should_resize(&self, img_width: u32) -> bool {
-    match self.operation {
-      Operation::Full => img_width > self.width_to,
-      _ => img_width == self.width_from,
+  /// Whether an image has the same aspect ratio as `reference`, give or take a pixel of height.
+  pub fn matches_reference(&self, img_width: u32, img_height: u32) -> bool {
+    let [ref_w, ref_h] = self.reference;
+    if ref_w == 0 || ref_h == 0 {
+      return true;
+    }
+    let expected_h = img_width as f64 * ref_h as f64 / ref_w as f64;
+    (img_height as f64 - expected_h).abs() <= 1.0
+  }
+
+  /// Resolves the area of `region` to pixels of an image of `img_width`x`img_height`, or `None` if it does not fit.
+  pub fn region_area(&self, region: &BlurRegion, img_width: u32, img_height: u32) -> Option<[u32; 4]> {
+    self.area(region.area, region.anchor, img_width, img_height)
+  }
+
+  /// Resolves `area`, measured from `anchor`, to pixels of an image of `img_width`x`img_height`, or `None` if it does
+  /// not fit or is scaled down to nothing.
+  pub fn area(&self, area: [Coord; 4], anchor: Anchor, img_width: u32, img_height: u32) -> Option<[u32; 4]> {
+    let [x, y, bw, bh] = area;
+    let (x, bw) = (self.coord(x, img_width, img_width), self.coord(bw, img_width, img_width));
+    let (y, bh) = (self.coord(y, img_width, img_height), self.coord(bh, img_width, img_height));
+    let left = match anchor {
+      Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => x,
+      Anchor::Top | Anchor::Center | Anchor::Bottom => img_width.checked_sub(bw)? / 2 + x,
+      Anchor::TopRight | Anchor::Right | Anchor::BottomRight => img_width.checked_sub(bw + x)?,
+    };
+    let top = match anchor {
+      Anchor::TopLeft | Anchor::Top | Anchor::TopRight => y,
+      Anchor::Left | Anchor::Center | Anchor::Right => img_height.checked_sub(bh)? / 2 + y,
+      Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => img_height.checked_sub(bh + y)?,
+    };
+    (bw > 0 && bh > 0 && left + bw <= img_width && top + bh <= img_height).then_some([left, top, bw, bh])
+  }
+
+  /// Converts `coord` to pixels of an image `img_width` wide, whose side along the axis of `coord` is `img_side` long.
+  fn coord(&self, coord: Coord, img_width: u32, img_side: u32) -> u32 {
+    match coord {
+      Coord::Px(v) => self.scale(v, img_width),
+      Coord::Percent(p) => (p / 100.0 * img_side as f64).round() as u32,
+    }
+  }
+
+  /// Scales `value`, given in `reference` pixels, to an image `img_width` wide.
+  pub fn scale(&self, value: u32, img_width: u32) -> u32 {
+    match self.reference[0] {
+      0 => value,
+      ref_w => (value as f64 * img_width as f64 / ref_w as f64).round() as u32,
+    }
+  }
+}
+
+#[cfg(test)]
+impl MergedOption {
+  /// Options of a `full` layer run on `target` that keeps images as they are.
+  pub(crate) fn for_tests(target: &std::path::Path) -> MergedOption {
+    MergedOption {
+      game: "none".into(),
+      layer: "full".into(),
+      allow_unblurred: false,
+      auto_crop_threshold: 24,
+      blur: Vec::new(),
+      crop_anchor: Anchor::Center,
+      crop_aspect: None,
+      crop_height: 0,
+      crop_pos: CropPosition::Full,
+      incremental: false,
+      jobs: 1,
+      originals: OriginalsAction::Keep,
+      output: OutputOption::default(),
+      reference: [0, 0],
+      resize: None,
+      resize_filter: ResizeFilter::default(),
+      save_at_parent: false,
+      scan: ScanOption::default(),
+      target: target.to_path_buf(),
+      width_from: 0,
+      width_to: 0,
+    }
+  }
+}
+
+/// Area to redact, given as `[x, y, width, height]` measured from `anchor`.
+/// Written as a plain `[x, y, width, height]` array, or as a table when anything but `area` is set.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "BlurRegionRepr", into = "BlurRegionRepr")]
+pub struct BlurRegion {
+  pub area: [Coord; 4],
+  pub anchor: Anchor,
+  pub style: RedactStyle,
+  /// 0 uses [`RedactStyle::default_strength`]
+  pub strength: u32,
+  /// RGB color of [`RedactStyle::Solid`]
+  pub color: [u8; 3],
+}
+
+impl BlurRegion {
+  pub fn strength(&self) -> u32 {
+    if self.strength == 0 { self.style.default_strength() } else { self.strength }
+  }
+}
+
+impl From<[Coord; 4]> for BlurRegion {
+  fn from(area: [Coord; 4]) -> Self {
+    BlurRegion { area, ..Default::default() }
+  }
+}
+
+impl From<[u32; 4]> for BlurRegion {
+  fn from(area: [u32; 4]) -> Self {
+    BlurRegion::from(area.map(Coord::Px))
+  }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum BlurRegionRepr {
+  Area([Coord; 4]),
+  Table(BlurRegionTable),
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct BlurRegionTable {
+  area: [Coord; 4],
+  #[serde(skip_serializing_if = "is_default")]
+  anchor: Anchor,
+  style: RedactStyle,
+  #[serde(skip_serializing_if = "is_default")]
+  strength: u32,
+  #[serde(skip_serializing_if = "is_default")]
+  color: [u8; 3],
+}
+
+impl From<BlurRegionRepr> for BlurRegion {
+  fn from(repr: BlurRegionRepr) -> Self {
+    match repr {
+      BlurRegionRepr::Area(area) => BlurRegion::from(area),
+      BlurRegionRepr::Table(t) => {
+        BlurRegion { area: t.area, anchor: t.anchor, style: t.style, strength: t.strength, color: t.color }
+      },
+    }
+  }
+}
+
+impl From<BlurRegion> for BlurRegionRepr {
+  fn from(r: BlurRegion) -> Self {
+    if r == BlurRegion::from(r.area) {
+      BlurRegionRepr::Area(r.area)
+    } else {
+      BlurRegionRepr::Table(BlurRegionTable {
+        area: r.area,
+        anchor: r.anchor,
+        style: r.style,
+        strength: r.strength,
+        color: r.color,
+      })
+    }
+  }
+}
+
+/// Width to height ratio of a crop, written as `"21:9"`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AspectRatio {
+  pub width: u32,
+  pub height: u32,
+}
+
+impl AspectRatio {
+  /// Returns the largest `[x, y, width, height]` of this ratio within `area`, placed at `anchor`.
+  pub fn fit(self, area: [u32; 4], anchor: Anchor) -> [u32; 4] {
+    let [x, y, w, h] = area;
+    let (rw, rh) = (self.width as u64, self.height as u64);
+    let (cw, ch) = if w as u64 * rh > h as u64 * rw {
+      (((h as u64 * rw + rh / 2) / rh) as u32, h)
+    } else {
+      (w, ((w as u64 * rh + rw / 2) / rw) as u32)
+    };
+    let (cw, ch) = (cw.clamp(1, w.max(1)), ch.clamp(1, h.max(1)));
+    let left = match anchor {
+      Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0,
+      Anchor::Top | Anchor::Center | Anchor::Bottom => (w - cw) / 2,
+      Anchor::TopRight | Anchor::Right | Anchor::BottomRight => w - cw,
+    };
+    let top = match anchor {
+      Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0,
+      Anchor::Left | Anchor::Center | Anchor::Right => (h - ch) / 2,
+      Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => h - ch,
+    };
+    [x + left, y + top, cw, ch]
+  }
+}
+
+impl FromStr for AspectRatio {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let err = || format!("Aspect ratio must be two positive integers like '21:9': '{}'", s);
+    let (w, h) = s.split_once(':').ok_or_else(err)?;
+    match (w.trim().parse::<u32>(), h.trim().parse::<u32>()) {
+      (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok(AspectRatio { width, height }),
+      _ => Err(err()),
+    }
+  }
+}
+
+impl fmt::Display for AspectRatio {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.width, self.height)
+  }
+}
+
+impl TryFrom<String> for AspectRatio {
+  type Error = String;
+
+  fn try_from(s: String) -> Result<Self, Self::Error> {
+    s.parse()
+  }
+}
+
+impl From<AspectRatio> for String {
+  fn from(ratio: AspectRatio) -> Self {
+    ratio.to_string()
+  }
+}
+
+/// Coordinate of a [`BlurRegion`], either in `reference` pixels like `40`, or in percent of the image like `"2.5%"`.
+/// `x` and `width` are percent of the image width, `y` and `height` of the image height.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "CoordRepr", into = "CoordRepr")]
+pub enum Coord {
+  Px(u32),
+  Percent(f64),
+}
+
+impl Default for Coord {
+  fn default() -> Self {
+    Coord::Px(0)
+  }
+}
+
+impl FromStr for Coord {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.trim();
+    match s.strip_suffix('%') {
+      Some(p) => match p.trim().parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(Coord::Percent(p)),
+        _ => Err(format!("Percentage must be from 0% to 100%: '{}'", s)),
+      },
+      None => {
+        s.parse::<u32>().map(Coord::Px).map_err(|_| format!("Must be an unsigned integer or a percentage: '{}'", s))
+      },
+    }
+  }
+}
+
+impl fmt::Display for Coord {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Coord::Px(v) => write!(f, "{}", v),
+      Coord::Percent(p) => write!(f, "{}%", p),
+    }
+  }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CoordRepr {
+  Px(u32),
+  Text(String),
+}
+
+impl TryFrom<CoordRepr> for Coord {
+  type Error = String;
+
+  fn try_from(repr: CoordRepr) -> Result<Self, Self::Error> {
+    match repr {
+      CoordRepr::Px(v) => Ok(Coord::Px(v)),
+      CoordRepr::Text(s) => s.parse(),
+    }
+  }
+}
+
+impl From<Coord> for CoordRepr {
+  fn from(c: Coord) -> Self {
+    match c {
+      Coord::Px(v) => CoordRepr::Px(v),
+      Coord::Percent(_) => CoordRepr::Text(c.to_string()),
+    }
+  }
+}
+
+fn is_default<T: Default + PartialEq>(v: &T) -> bool {
+  *v == T::default()
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanOption {
+  /// Search subdirectories too
+  pub recursive: bool,
+  /// Only images matching any of these globs are converted. Every image is converted if empty.
+  pub include: Vec<String>,
+  /// Images and directories matching any of these globs are skipped
+  pub exclude: Vec<String>,
+  /// Recreate the subdirectory tree of the input under the output directory
+  pub mirror: bool,
+}
+
+impl ScanOption {
+  /// Returns an error message for the first invalid glob in `include` or `exclude`.
+  pub fn validate(&self) -> Result<(), String> {
+    for pattern in self.include.iter().chain(&self.exclude) {
+      Glob::new(pattern).map_err(|e| format!("Invalid glob '{}': {}", pattern, e))?;
+    }
+    Ok(())
+  }
+
+  pub fn include_set(&self) -> GlobSet {
+    glob_set(&self.include)
+  }
+
+  pub fn exclude_set(&self) -> GlobSet {
+    glob_set(&self.exclude)
+  }
+}
+
+/// Invalid globs are ignored; check them with [`ScanOption::validate`] beforehand.
+fn glob_set(patterns: &[String]) -> GlobSet {
+  let mut builder = GlobSetBuilder::new();
+  for glob in patterns.iter().filter_map(|p| Glob::new(p).ok()) {
+    builder.add(glob);
+  }
+  builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputOption {
+  pub format: OutputFormat,
+  /// Output file name without extension, see [`FilenameTemplate`]
+  pub filename: String,
+  pub on_conflict: ConflictPolicy,
+  pub webp: WebPOption,
+  pub png: PngOption,
+  pub jpeg: JpegOption,
+  pub avif: AvifOption,
+}
+
+impl Default for OutputOption {
+  fn default() -> Self {
+    OutputOption {
+      format: OutputFormat::WebP,
+      filename: "{stem}".into(),
+      on_conflict: ConflictPolicy::default(),
+      webp: WebPOption::default(),
+      png: PngOption::default(),
+      jpeg: JpegOption::default(),
+      avif: AvifOption::default(),
+    }
+  }
+}
+
+impl OutputOption {
+  /// Returns an error message if `filename` is not a valid template, or metadata is asked of a format that cannot
+  /// carry it.
+  pub fn validate(&self) -> Result<(), String> {
+    FilenameTemplate::parse(&self.filename)?;
+    if self.avif.metadata.icc {
+      return Err("AVIF output cannot carry an ICC profile; set 'icc = false' in [output.avif.metadata]".into());
+    }
+    for (key, value, range) in [
+      ("[output.jpeg] quality", self.jpeg.quality, 1..=100),
+      ("[output.avif] quality", self.avif.quality, 1..=100),
+      ("[output.avif] speed", self.avif.speed, 1..=10),
+    ] {
+      if !range.contains(&value) {
+        return Err(format!("{} must be from {} to {}, not {}", key, range.start(), range.end(), value));
+      }
+    }
+    Ok(())
+  }
+
+  /// Writes no metadata in any format.
+  pub fn strip_metadata(&mut self) {
+    for metadata in [&mut self.webp.metadata, &mut self.png.metadata, &mut self.jpeg.metadata, &mut self.avif.metadata]
+    {
+      *metadata = MetadataOption { icc: false, exif: Vec::new() };
+    }
+  }
+}
+
+/// Mirror of libwebp's `WebPConfig`. Refer to `libwebp/src/webp/encode.h` for the meaning of each field.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebPOption {
+  pub lossless: bool,
+  pub quality: f32,
+  pub method: i32,
+  pub image_hint: WebPImageHint,
+  pub target_size: i32,
+  pub target_psnr: f32,
+  pub segments: i32,
+  pub sns_strength: i32,
+  pub filter_strength: i32,
+  pub filter_sharpness: i32,
+  pub filter_type: i32,
+  pub autofilter: bool,
+  pub alpha_compression: i32,
+  pub alpha_filtering: i32,
+  pub alpha_quality: i32,
+  pub pass: i32,
+  pub show_compressed: bool,
+  pub preprocessing: i32,
+  pub partitions: i32,
+  pub partition_limit: i32,
+  pub emulate_jpeg_size: bool,
+  pub thread_level: i32,
+  pub low_memory: bool,
+  pub near_lossless: i32,
+  pub exact: bool,
+  pub use_delta_palette: bool,
+  pub use_sharp_yuv: bool,
+  pub qmin: i32,
+  pub qmax: i32,
+  /// Not part of libwebp's `WebPConfig`, written as RIFF chunks after encoding
+  pub metadata: MetadataOption,
+}
+
+impl WebPOption {
+  /// Level used for near-lossless mode when `near_lossless` is left at 100 (off)
+  const DEFAULT_NEAR_LOSSLESS: i32 = 60;
+
+  /// Sets `lossless` and `near_lossless` according to `mode`.
+  pub fn set_mode(&mut self, mode: WebPMode) {
+    match mode {
+      WebPMode::Lossy => self.lossless = false,
+      WebPMode::Lossless => {
+        self.lossless = true;
+        self.near_lossless = 100;
+      },
+      WebPMode::NearLossless => {
+        self.lossless = true;
+        if self.near_lossless >= 100 {
+          self.near_lossless = Self::DEFAULT_NEAR_LOSSLESS;
+        }
+      },
+    }
+  }
+}
+
+impl Default for WebPOption {
+  /// libwebp defaults, with the values of PICTURE preset applied
+  fn default() -> Self {
+    WebPOption {
+      lossless: false,
+      quality: 85.0, // between 0 and 100. For lossy, 0 gives the smallest size and 100 the largest.
+      method: 6,     // quality/speed trade-off (0=fast, 6=slower-better)
+      image_hint: WebPImageHint::Default,
+      target_size: 0,
+      target_psnr: 0.0,
+      segments: 4,
+      sns_strength: 80,    // Spatial Noise Shaping. 0=off, 100=maximum. (PICTURE)
+      filter_strength: 35, // range: [0 = off .. 100 = strongest] (PICTURE)
+      filter_sharpness: 4, // range: [0 = off .. 7 = least sharp] (PICTURE)
+      filter_type: 1,
+      autofilter: false,
+      alpha_compression: 1,
+      alpha_filtering: 1,
+      alpha_quality: 100,
+      pass: 4, // number of entropy-analysis passes (in [1..10]).
+      show_compressed: false,
+      preprocessing: 2, // preprocessing filter: 0=none, 1=segment-smooth, 2=pseudo-random dithering (PICTURE)
+      partitions: 0,
+      partition_limit: 0,
+      emulate_jpeg_size: false,
+      thread_level: 1, // If non-zero, try and use multi-threaded encoding.
+      low_memory: false,
+      near_lossless: 100,
+      exact: false,
+      use_delta_palette: false,
+      use_sharp_yuv: false,
+      qmin: 0,
+      qmax: 100,
+      metadata: MetadataOption::default(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PngOption {
+  pub compression: PngCompression,
+  pub filter: PngFilter,
+  pub metadata: MetadataOption,
+}
+
+impl Default for PngOption {
+  fn default() -> Self {
+    PngOption { compression: PngCompression::Best, filter: PngFilter::Adaptive, metadata: MetadataOption::default() }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JpegOption {
+  /// between 1 and 100
+  pub quality: u8,
+  pub metadata: MetadataOption,
+}
+
+impl Default for JpegOption {
+  fn default() -> Self {
+    JpegOption { quality: 90, metadata: MetadataOption::default() }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AvifOption {
+  /// between 1 and 100
+  pub quality: u8,
+  /// between 1 (slowest-best) and 10 (fastest)
+  pub speed: u8,
+  /// AVIF output cannot carry an ICC profile, so `icc` must be false
+  pub metadata: MetadataOption,
+}
+
+impl Default for AvifOption {
+  fn default() -> Self {
+    AvifOption { quality: 80, speed: 4, metadata: MetadataOption { icc: false, exif: Vec::new() } }
+  }
+}
+
+/// Metadata carried from the input into the output. Everything else, such as XMP, is stripped.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataOption {
+  /// Embed the ICC profile of the input
+  pub icc: bool,
+  /// EXIF fields copied from the input
+  pub exif: Vec<ExifField>,
+}
+
+impl Default for MetadataOption {
+  fn default() -> Self {
+    MetadataOption { icc: true, exif: Vec::new() }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ratio(s: &str) -> AspectRatio {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn fit_cuts_width_of_wider_area() {
+    let area = [0, 0, 1920, 1080];
+    assert_eq!(ratio("1:1").fit(area, Anchor::Center), [420, 0, 1080, 1080]);
+    assert_eq!(ratio("1:1").fit(area, Anchor::TopLeft), [0, 0, 1080, 1080]);
+    assert_eq!(ratio("1:1").fit(area, Anchor::Bottom), [420, 0, 1080, 1080]);
+    assert_eq!(ratio("1:1").fit(area, Anchor::Right), [840, 0, 1080, 1080]);
+  }
+
+  #[test]
+  fn fit_cuts_height_of_taller_area() {
+    let area = [0, 0, 1920, 1080];
+    // 1920 * 9 / 21 = 822.86
+    assert_eq!(ratio("21:9").fit(area, Anchor::Center), [0, 128, 1920, 823]);
+    assert_eq!(ratio("21:9").fit(area, Anchor::TopRight), [0, 0, 1920, 823]);
+    assert_eq!(ratio("21:9").fit(area, Anchor::Left), [0, 128, 1920, 823]);
+    assert_eq!(ratio("21:9").fit(area, Anchor::BottomLeft), [0, 257, 1920, 823]);
+  }
+
+  #[test]
+  fn fit_stays_within_area() {
+    assert_eq!(ratio("16:9").fit([10, 20, 1920, 1080], Anchor::Center), [10, 20, 1920, 1080]);
+    assert_eq!(ratio("1:1").fit([100, 50, 400, 200], Anchor::BottomRight), [300, 50, 200, 200]);
+    assert_eq!(ratio("21:9").fit([5, 5, 1, 1], Anchor::Center), [5, 5, 1, 1]);
+  }
+
+  #[test]
+  fn area_scales_from_reference() {
+    let mo = MergedOption { reference: [1920, 1080], ..MergedOption::for_tests(std::path::Path::new("shots")) };
+    let area = [Coord::Px(40), Coord::Px(1054), Coord::Px(330), Coord::Px(22)];
+    assert_eq!(mo.area(area, Anchor::TopLeft, 1920, 1080), Some([40, 1054, 330, 22]));
+    assert_eq!(mo.area(area, Anchor::TopLeft, 3840, 2160), Some([80, 2108, 660, 44]));
+    // 22 rows of 1080 are less than half a row of 18
+    assert_eq!(mo.area(area, Anchor::TopLeft, 32, 18), None);
+    assert_eq!(mo.area([Coord::Px(0), Coord::Px(0), Coord::Px(0), Coord::Px(10)], Anchor::Center, 1920, 1080), None);
+  }
+
+  #[test]
+  fn validate_checks_encoder_ranges() {
+    let output = OutputOption::default();
+    assert_eq!(output.validate(), Ok(()));
+
+    let mut bad = output.clone();
+    bad.avif.speed = 0;
+    assert_eq!(bad.validate().unwrap_err(), "[output.avif] speed must be from 1 to 10, not 0");
+    bad.avif.speed = 11;
+    assert!(bad.validate().is_err());
+
+    let mut bad = output.clone();
+    bad.avif.quality = 0;
+    assert_eq!(bad.validate().unwrap_err(), "[output.avif] quality must be from 1 to 100, not 0");
+
+    let mut bad = output;
+    bad.jpeg.quality = 101;
+    assert_eq!(bad.validate().unwrap_err(), "[output.jpeg] quality must be from 1 to 100, not 101");
+  }
+
+  #[test]
+  fn parses_aspect_ratio() {
+    assert_eq!(ratio(" 21 : 9 ").to_string(), "21:9");
+    for s in ["21", "0:9", "21:0", "-1:1", "a:b"] {
+      assert!(s.parse::<AspectRatio>().is_err(), "{}", s);
     }
   }
 }
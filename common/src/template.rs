@@ -0,0 +1,235 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+
+use crate::structs::MergedOption;
+
+/// Characters that cannot be part of a file name on Windows, the path separators included
+const RESERVED: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Output file name without extension, such as `{game}-{layer}-{index:03}-{stem}` or `{date}_{stem}`.
+/// `{{` and `}}` are literal braces.
+#[derive(Debug)]
+pub struct FilenameTemplate {
+  parts: Vec<Part>,
+}
+
+#[derive(Debug)]
+enum Part {
+  Text(String),
+  Field(Field, Option<String>),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+  Game,
+  Layer,
+  Stem,
+  Index,
+  Width,
+  Height,
+  Mtime,
+  Date,
+  Time,
+}
+
+impl Field {
+  fn from_name(name: &str) -> Option<Field> {
+    Some(match name {
+      "game" => Field::Game,
+      "layer" => Field::Layer,
+      "stem" => Field::Stem,
+      "index" => Field::Index,
+      "width" => Field::Width,
+      "height" => Field::Height,
+      "mtime" => Field::Mtime,
+      "date" => Field::Date,
+      "time" => Field::Time,
+      _ => return None,
+    })
+  }
+
+  /// strftime format of a date field without a spec
+  fn default_format(self) -> &'static str {
+    match self {
+      Field::Mtime => "%Y%m%d-%H%M%S",
+      Field::Time => "%H%M%S",
+      _ => "%Y-%m-%d",
+    }
+  }
+}
+
+impl FilenameTemplate {
+  pub fn parse(s: &str) -> Result<FilenameTemplate, String> {
+    let err = |msg: String| format!("Invalid filename template '{}': {}", s, msg);
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+      match c {
+        '{' if chars.as_str().starts_with('{') => {
+          chars.next();
+          text.push('{');
+        },
+        '}' if chars.as_str().starts_with('}') => {
+          chars.next();
+          text.push('}');
+        },
+        '{' => {
+          let rest = chars.as_str();
+          let end = rest.find('}').ok_or_else(|| err("'{' is not closed".into()))?;
+          let (name, spec) = match rest[..end].split_once(':') {
+            Some((name, spec)) => (name, Some(spec.to_string())),
+            None => (&rest[..end], None),
+          };
+          let field = Field::from_name(name).ok_or_else(|| {
+            err(format!(
+              "unknown placeholder '{{{}}}'. Available placeholders: game, layer, stem, index, width, height, mtime, \
+               date, time",
+              name
+            ))
+          })?;
+          check_spec(field, spec.as_deref()).map_err(err)?;
+          if !text.is_empty() {
+            parts.push(Part::Text(std::mem::take(&mut text)));
+          }
+          parts.push(Part::Field(field, spec));
+          chars = rest[end + 1..].chars();
+        },
+        '}' => return Err(err("'}' must be written as '}}'".into())),
+        c if RESERVED.contains(&c) || c.is_control() => {
+          return Err(err(format!("'{}' cannot be part of a file name", c.escape_default())));
+        },
+        c => text.push(c),
+      }
+    }
+    if !text.is_empty() {
+      parts.push(Part::Text(text));
+    }
+    if parts.is_empty() {
+      return Err(err("must not be empty".into()));
+    }
+    Ok(FilenameTemplate { parts })
+  }
+
+  /// Fills in the template for `f`, the `index`th image of the run started at `started`.
+  pub fn render(&self, f: &Path, index: usize, started: DateTime<Local>, mo: &MergedOption) -> Result<String, String> {
+    let mut dimensions = None;
+    let mut name = String::new();
+    for part in &self.parts {
+      match part {
+        Part::Text(text) => name.push_str(text),
+        Part::Field(field, spec) => {
+          let value = match field {
+            Field::Game => mo.game.clone(),
+            Field::Layer => mo.layer.clone(),
+            Field::Stem => f.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+            Field::Index => pad(index as u64, spec.as_deref()),
+            Field::Width | Field::Height => {
+              if dimensions.is_none() {
+                dimensions =
+                  Some(image::image_dimensions(f).map_err(|e| format!("Failed to open '{}': {}", f.display(), e))?);
+              }
+              let (w, h) = dimensions.unwrap();
+              let value = if matches!(field, Field::Width) { w } else { h };
+              pad(value as u64, spec.as_deref())
+            },
+            Field::Mtime => {
+              let mtime = fs::metadata(f)
+                .and_then(|meta| meta.modified())
+                .map_err(|e| format!("Failed to read modification time of '{}': {}", f.display(), e))?;
+              DateTime::<Local>::from(mtime).format(spec.as_deref().unwrap_or(field.default_format())).to_string()
+            },
+            Field::Date | Field::Time => started.format(spec.as_deref().unwrap_or(field.default_format())).to_string(),
+          };
+          name.push_str(&value);
+        },
+      }
+    }
+    if let Some(c) = name.chars().find(|c| RESERVED.contains(c) || c.is_control()) {
+      return Err(format!("Output file name '{}' of '{}' contains '{}'", name, f.display(), c.escape_default()));
+    }
+    Ok(name)
+  }
+}
+
+/// Numbers take a width, zero-padded if it starts with '0'. Dates take a strftime format. Text takes no spec.
+fn check_spec(field: Field, spec: Option<&str>) -> Result<(), String> {
+  let Some(spec) = spec else {
+    return Ok(());
+  };
+  match field {
+    Field::Index | Field::Width | Field::Height => match spec.parse::<usize>() {
+      Ok(_) => Ok(()),
+      Err(_) => Err(format!("'{}' is not a width, such as '03'", spec)),
+    },
+    Field::Mtime | Field::Date | Field::Time => match StrftimeItems::new(spec).any(|item| item == Item::Error) {
+      true => Err(format!("'{}' is not a strftime format, such as '%Y%m%d'", spec)),
+      false => Ok(()),
+    },
+    Field::Game | Field::Layer | Field::Stem => Err(format!("'{}' takes no format", spec)),
+  }
+}
+
+fn pad(value: u64, spec: Option<&str>) -> String {
+  match spec {
+    Some(spec) if spec.starts_with('0') => format!("{:0width$}", value, width = spec.parse().unwrap_or(0)),
+    Some(spec) => format!("{:width$}", value, width = spec.parse().unwrap_or(0)),
+    None => value.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+
+  fn render(template: &str, index: usize) -> Result<String, String> {
+    let mo = MergedOption::for_tests(Path::new("shots"));
+    let started = Local.with_ymd_and_hms(2024, 5, 6, 7, 8, 9).unwrap();
+    FilenameTemplate::parse(template)?.render(Path::new("shots/Screenshot 1.png"), index, started, &mo)
+  }
+
+  #[test]
+  fn fills_in_placeholders() {
+    assert_eq!(render("{game}-{layer}-{stem}", 1).unwrap(), "none-full-Screenshot 1");
+    assert_eq!(render("{date}_{time}", 1).unwrap(), "2024-05-06_070809");
+    assert_eq!(render("{date:%Y%m%d}{time:%H}", 1).unwrap(), "2024050607");
+  }
+
+  #[test]
+  fn pads_numbers() {
+    assert_eq!(render("{index}", 7).unwrap(), "7");
+    assert_eq!(render("{index:03}", 7).unwrap(), "007");
+    assert_eq!(render("{index:3}", 7).unwrap(), "  7");
+    assert_eq!(render("{index:02}", 1234).unwrap(), "1234");
+  }
+
+  #[test]
+  fn escapes_braces() {
+    assert_eq!(render("{{stem}}", 1).unwrap(), "{stem}");
+    assert_eq!(render("{{{stem}}}", 1).unwrap(), "{Screenshot 1}");
+    assert!(FilenameTemplate::parse("{stem").unwrap_err().contains("'{' is not closed"));
+    assert!(FilenameTemplate::parse("stem}").unwrap_err().contains("'}' must be written as '}}'"));
+  }
+
+  #[test]
+  fn rejects_bad_templates() {
+    let err = FilenameTemplate::parse("{name}").unwrap_err();
+    assert!(err.contains("unknown placeholder '{name}'"), "{}", err);
+    assert!(FilenameTemplate::parse("{index:ab}").unwrap_err().contains("is not a width"));
+    assert!(FilenameTemplate::parse("{date:%Q}").unwrap_err().contains("is not a strftime format"));
+    assert!(FilenameTemplate::parse("{stem:3}").unwrap_err().contains("takes no format"));
+    assert!(FilenameTemplate::parse("a/b").unwrap_err().contains("cannot be part of a file name"));
+    assert!(FilenameTemplate::parse("").unwrap_err().contains("must not be empty"));
+  }
+
+  #[test]
+  fn rejects_reserved_characters_in_values() {
+    let err = render("{date:%H:%M}", 1).unwrap_err();
+    assert!(err.contains("contains ':'"), "{}", err);
+  }
+}
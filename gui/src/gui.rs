@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 use std::process::exit;
 
+use common::pipeline::ImageMsg;
 use fltk::app::{App, Receiver};
 use fltk::browser::Browser;
 use fltk::enums::{Align, Font};
@@ -14,13 +15,6 @@ use native_dialog::{DialogBuilder, MessageLevel};
 
 use crate::MO;
 
-pub enum ImageMsg {
-  Done { filename: String },
-  Error { text: String },
-  Finished,
-  Progress { current: usize, total: usize, filename: String },
-}
-
 pub fn error_message(s: &str) {
   DialogBuilder::message()
     .set_level(MessageLevel::Error)
@@ -65,8 +59,8 @@ pub fn run_gui(from: PathBuf, to: PathBuf, imgs: Vec<PathBuf>, app: App, r: Rece
 
   // display value
   let mo = MO.get().unwrap();
-  let game = mo.game;
-  let operation = mo.operation;
+  let game = &mo.game;
+  let layer = &mo.layer;
   let imgs_len = imgs.len();
   let from_string = from.to_string_lossy();
   let from_normalized = from_string.replace("\\", "/");
@@ -98,7 +92,7 @@ pub fn run_gui(from: PathBuf, to: PathBuf, imgs: Vec<PathBuf>, app: App, r: Rece
   });
 
   // third, fourth row
-  let job_info_text = format!("Game: {:?} | Operation: {:?}", game, operation);
+  let job_info_text = format!("Game: {} | Layer: {}", game, layer);
   let current_info_text = format!("Total: {} | Current: {}", imgs_len, 0);
 
   let _lbl_job_info = label(12, 69, 385, 12, job_info_text.as_str()).with_align(Align::Left | Align::Inside);
@@ -171,6 +165,17 @@ pub fn run_gui(from: PathBuf, to: PathBuf, imgs: Vec<PathBuf>, app: App, r: Rece
           brw_log.select(size);
           brw_log.bottom_line(size);
         },
+        ImageMsg::Skipped { filename } => {
+          // log
+          let size = brw_log.size();
+          brw_log.set_text(size, format!("↷ {} SKIPPED", filename).as_str());
+          brw_log.select(size);
+          brw_log.bottom_line(size);
+        },
+        ImageMsg::Warning { text } => {
+          // log
+          brw_log.add(format!("⚠ {}", text).as_str());
+        },
       }
     }
   }
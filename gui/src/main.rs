@@ -1,23 +1,20 @@
 #![cfg_attr(all(target_os = "windows", not(debug_assertions)), windows_subsystem = "windows")]
 
 mod gui;
-mod image;
 
-use std::fs::create_dir_all;
 use std::io::{Read, stdin};
-use std::path::PathBuf;
 use std::process::exit;
 use std::sync::OnceLock;
 use std::thread;
 
-use common::dir_collect_image;
+use common::pipeline::{ImageMsg, Run, process_image};
 use common::structs::MergedOption;
+use common::{dir_collect_image, output_dir};
 use fltk::app;
 use fltk::app::{Receiver, Sender};
 use fltk_theme::{ColorTheme, color_themes};
 
-use crate::gui::{ImageMsg, error_message, run_gui};
-use crate::image::process_image;
+use crate::gui::{error_message, run_gui};
 
 static MO: OnceLock<MergedOption> = OnceLock::new();
 
@@ -41,7 +38,7 @@ fn main() {
   MO.set(mo.clone()).expect("MO already initialized");
 
   // get image file list
-  let imgs = dir_collect_image(&mo.target.to_path_buf());
+  let imgs = dir_collect_image(&mo.target, &mo.scan);
   if imgs.is_empty() {
     error_message(format!("There is no image file in '{:?}'", &mo.target).as_str());
     exit(1);
@@ -63,26 +60,10 @@ fn main() {
   let imgs_t = imgs.clone();
   let to_t = to.clone();
   thread::spawn(move || {
-    process_image(imgs_t, &mo, to_t, s);
+    process_image(imgs_t, &mo, to_t, Run::default(), &|msg| s.send(msg));
   });
 
   // spawn GUI and pass receiver
   // This will handle Window, not App
   run_gui(from, to, imgs, app, r);
 }
-
-fn output_dir(mo: &MergedOption) -> PathBuf {
-  let target = &mo.target;
-  let candidate = if mo.save_at_parent {
-    target.parent().and_then(|p| if p.parent().is_some() { Some(p.to_path_buf()) } else { None }).unwrap_or_else(|| {
-      let mut s = target.clone();
-      s.set_file_name(format!("{}-converted", target.file_name().unwrap().to_string_lossy()));
-      s
-    })
-  } else {
-    target.join("converted")
-  };
-
-  create_dir_all(&candidate).ok();
-  candidate
-}